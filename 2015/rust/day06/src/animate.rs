@@ -0,0 +1,261 @@
+/*
+ * Frame by frame export of the light show.
+ *
+ * The grid is rendered after every `stride` instructions and once more after the last one if it
+ * did not fall on a stride. Brightnesses are normalized against the brightest light of all frames,
+ * which takes a first pass over the instructions, so a color means the same in every frame. Frames are written as a numbered PPM sequence which tools like
+ * ffmpeg or ImageMagick can turn into a video or an animated GIF, i.e.
+ *
+ *   convert -delay 10 frames/frame_*.ppm show.gif
+ */
+use std::{fs, io, path::Path};
+
+use super::*;
+
+// Restricts a raster to the cells within `locations`
+pub struct Cropped<'a, R: Raster> {
+    raster: &'a R,
+    locations: &'a Locations,
+}
+
+impl<'a, R: Raster> Cropped<'a, R> {
+    pub fn new(raster: &'a R, locations: &'a Locations) -> Self {
+        Self { raster, locations }
+    }
+}
+
+impl<'a, R: Raster> Raster for Cropped<'a, R> {
+    fn ncols(&self) -> usize {
+        self.locations.through.0 - self.locations.from.0 + 1
+    }
+
+    fn nrows(&self) -> usize {
+        self.locations.through.1 - self.locations.from.1 + 1
+    }
+
+    fn light(&self, x: usize, y: usize) -> bool {
+        let from = self.locations.from;
+        self.raster.light(from.0 + x, from.1 + y)
+    }
+
+    fn dimmable(&self, x: usize, y: usize) -> u32 {
+        let from = self.locations.from;
+        self.raster.dimmable(from.0 + x, from.1 + y)
+    }
+}
+
+pub struct Animation {
+    pub stride: usize,
+    pub crop: Option<Locations>,
+    pub palette: Palette,
+    pub scale: usize,
+}
+
+// The brightest light of a raster
+fn max_dimmable(raster: &impl Raster) -> u32 {
+    (0..raster.nrows())
+        .flat_map(|y| (0..raster.ncols()).map(move |x| raster.dimmable(x, y)))
+        .max()
+        .unwrap_or(0)
+}
+
+impl Animation {
+    // Calls `on_frame` with the number of processed instructions and the grid of every frame
+    fn each_frame(
+        &self,
+        grid: &mut Grid,
+        instructions: &[Instruction],
+        mut on_frame: impl FnMut(usize, &Grid),
+    ) -> Result<(), Overflow> {
+        let stride = self.stride.max(1);
+        for (idx, instruction) in instructions.iter().enumerate() {
+            process_instruction(grid, instruction)?;
+            let processed = idx + 1;
+            if processed % stride == 0 || processed == instructions.len() {
+                on_frame(processed, grid);
+            }
+        }
+        Ok(())
+    }
+
+    // Calls `on_frame` with the number of processed instructions and the rendered frame
+    pub fn frames(
+        &self,
+        grid: &mut Grid,
+        instructions: &[Instruction],
+        mut on_frame: impl FnMut(usize, Image),
+    ) -> Result<(), Overflow> {
+        let max = match self.palette {
+            Palette::Lights => None,
+            Palette::Gray | Palette::Heat => {
+                let mut max = 0;
+                self.each_frame(&mut grid.clone(), instructions, |_, grid| {
+                    max = max.max(match &self.crop {
+                        Some(locations) => max_dimmable(&Cropped::new(grid, locations)),
+                        None => max_dimmable(grid),
+                    });
+                })?;
+                Some(max)
+            }
+        };
+        let (palette, scale) = (self.palette, self.scale);
+        self.each_frame(grid, instructions, |processed, grid| {
+            let image = match &self.crop {
+                Some(locations) => {
+                    render_against(&Cropped::new(grid, locations), palette, scale, max)
+                }
+                None => render_against(grid, palette, scale, max),
+            };
+            on_frame(processed, image);
+        })
+    }
+
+    // Writes frame_00000.ppm, frame_00001.ppm, ... into `dir` and returns the number of frames,
+    // an overflowing brightness is reported as invalid data
+    pub fn save(
+        &self,
+        grid: &mut Grid,
+        instructions: &[Instruction],
+        dir: &str,
+    ) -> io::Result<usize> {
+        if let Some(crop) = &self.crop {
+            check_locations(crop, grid.ncols, grid.nrows)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        }
+        fs::create_dir_all(dir)?;
+        let mut nframes = 0;
+        let mut result = Ok(());
+        self.frames(grid, instructions, |_, image| {
+            if result.is_ok() {
+                let path = Path::new(dir).join(format!("frame_{:05}.ppm", nframes));
+                result = image.save(&path.to_string_lossy());
+                nframes += 1;
+            }
+        })
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        result.map(|_| nframes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INSTRUCTIONS: &str = "\
+        \x20turn on 0,0 through 3,3\n\
+        \x20turn off 1,1 through 2,2\n\
+        \x20toggle 0,0 through 0,3\n\
+        \x20toggle 0,0 through 3,0\n\
+        \x20turn on 2,2 through 2,2\
+        ";
+
+    fn lit_pixels(image: &Image) -> usize {
+        image.pixels.iter().filter(|x| **x != [0, 0, 0]).count()
+    }
+
+    #[test]
+    fn frames_at_stride() {
+        let animation = Animation {
+            stride: 2,
+            crop: None,
+            palette: Palette::Lights,
+            scale: 1,
+        };
+        let mut frames = vec![];
+        let mut grid = Grid::new(4, 4).unwrap();
+        animation
+            .frames(
+                &mut grid,
+                &parse_input(INSTRUCTIONS).unwrap(),
+                |processed, image| frames.push((processed, lit_pixels(&image))),
+            )
+            .unwrap();
+        assert_eq!(frames, vec![(2, 12), (4, 6), (5, 7)]);
+    }
+
+    #[test]
+    fn cropped_frames() {
+        let animation = Animation {
+            stride: 1,
+            crop: parse_locations("1,1 through 2,3"),
+            palette: Palette::Lights,
+            scale: 1,
+        };
+        let mut frames = vec![];
+        let mut grid = Grid::new(4, 4).unwrap();
+        animation
+            .frames(
+                &mut grid,
+                &parse_input(INSTRUCTIONS).unwrap(),
+                |_, image| {
+                    assert_eq!((image.width, image.height), (2, 3));
+                    frames.push(lit_pixels(&image));
+                },
+            )
+            .unwrap();
+        assert_eq!(frames, vec![6, 2, 2, 2, 3]);
+    }
+
+    #[test]
+    fn brightness_builds_up() {
+        let animation = Animation {
+            stride: 1,
+            crop: None,
+            palette: Palette::Gray,
+            scale: 1,
+        };
+        let mut frames = vec![];
+        let mut grid = Grid::new(2, 1).unwrap();
+        let input = "turn on 0,0 through 0,0\nturn on 0,0 through 0,0\ndim by 2 0,0 through 1,0";
+        animation
+            .frames(&mut grid, &parse_input(input).unwrap(), |_, image| {
+                frames.push(image.pixels[0])
+            })
+            .unwrap();
+        assert_eq!(frames, vec![[128; 3], [255; 3], [0; 3]]);
+    }
+
+    #[test]
+    fn save_numbered_frames() {
+        let dir = std::env::temp_dir().join(format!("day06-animate-{}", std::process::id()));
+        let dir = dir.to_string_lossy().to_string();
+        let animation = Animation {
+            stride: 3,
+            crop: None,
+            palette: Palette::Gray,
+            scale: 2,
+        };
+        let mut grid = Grid::new(4, 4).unwrap();
+        let nframes = animation.save(&mut grid, &parse_input(INSTRUCTIONS).unwrap(), &dir);
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|x| x.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(nframes.unwrap(), 2);
+        assert_eq!(files, vec!["frame_00000.ppm", "frame_00001.ppm"]);
+    }
+
+    #[test]
+    fn invalid_crop() {
+        let dir = std::env::temp_dir().join(format!("day06-crop-{}", std::process::id()));
+        let dir = dir.to_string_lossy().to_string();
+        for crop in ["0,0 through 4,3", "2,2 through 1,1"].iter() {
+            let animation = Animation {
+                stride: 1,
+                crop: parse_locations(crop),
+                palette: Palette::Lights,
+                scale: 1,
+            };
+            let mut grid = Grid::new(4, 4).unwrap();
+            let error = animation
+                .save(&mut grid, &parse_input(INSTRUCTIONS).unwrap(), &dir)
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        // rejected before anything is written
+        assert!(!Path::new(&dir).exists());
+    }
+}
@@ -0,0 +1,223 @@
+/*
+ * Coordinate compressed alternative to the dense `Grid`.
+ *
+ * The x and y boundaries of all instruction rectangles split the grid into blocks in which every
+ * cell is always in the same state. Therefore we only need to track one light and one brightness
+ * per block and weigh it by the block's area when aggregating.
+ * The number of blocks only depends on the number of instructions and not on the grid's size,
+ * which makes this feasible for grids of 10^9 x 10^9 lights.
+ */
+use super::*;
+
+pub struct CompressedGrid {
+    // block i spans xs[i]..xs[i + 1]
+    xs: Vec<usize>,
+    ys: Vec<usize>,
+    lights: Vec<bool>,
+    dimmables: Vec<u32>,
+}
+
+fn boundaries(size: usize, ranges: impl Iterator<Item = (usize, usize)>) -> Vec<usize> {
+    let mut bounds = vec![0, size];
+    for (from, through) in ranges {
+        bounds.push(from);
+        bounds.push(through + 1);
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+    bounds
+}
+
+impl CompressedGrid {
+    // All instructions that will be processed need to be known upfront to derive the blocks
+    pub fn new(ncols: usize, nrows: usize, instructions: &[Instruction]) -> Self {
+        let rects: Vec<Locations> = instructions
+            .iter()
+            .flat_map(|x| x.shape.rects(ncols, nrows))
+            .collect();
+        let xs = boundaries(ncols, rects.iter().map(|x| (x.from.0, x.through.0)));
+        let ys = boundaries(nrows, rects.iter().map(|x| (x.from.1, x.through.1)));
+        let nblocks = (xs.len() - 1) * (ys.len() - 1);
+        Self {
+            xs,
+            ys,
+            lights: vec![false; nblocks],
+            dimmables: vec![0; nblocks],
+        }
+    }
+
+    fn block_range(bounds: &[usize], from: usize, through: usize) -> (usize, usize) {
+        let start = bounds
+            .binary_search(&from)
+            .expect("instruction was not known when the grid was created");
+        let end = bounds
+            .binary_search(&(through + 1))
+            .expect("instruction was not known when the grid was created");
+        (start, end)
+    }
+
+    pub fn process_instruction(&mut self, instruction: &Instruction) -> Result<(), Overflow> {
+        for rect in instruction.shape.rects(self.ncols(), self.nrows()) {
+            self.process_rect(instruction.typ, &rect)?;
+        }
+        Ok(())
+    }
+
+    fn process_rect(
+        &mut self,
+        typ: InstructionType,
+        locations: &Locations,
+    ) -> Result<(), Overflow> {
+        let Locations { from, through } = locations;
+        let (x_start, x_end) = CompressedGrid::block_range(&self.xs, from.0, through.0);
+        let (y_start, y_end) = CompressedGrid::block_range(&self.ys, from.1, through.1);
+        let nx = self.xs.len() - 1;
+
+        for y in y_start..y_end {
+            for x in x_start..x_end {
+                let idx = y * nx + x;
+                rules::apply(&Switch, typ, &mut self.lights[idx])?;
+                rules::apply(&Dimmer, typ, &mut self.dimmables[idx])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn areas(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        let nx = self.xs.len() - 1;
+        (0..self.lights.len()).map(move |idx| {
+            let (x, y) = (idx % nx, idx / nx);
+            let width = self.xs[x + 1] - self.xs[x];
+            let height = self.ys[y + 1] - self.ys[y];
+            (idx, width as u64 * height as u64)
+        })
+    }
+
+    pub fn ncols(&self) -> usize {
+        self.xs[self.xs.len() - 1]
+    }
+
+    pub fn nrows(&self) -> usize {
+        self.ys[self.ys.len() - 1]
+    }
+
+    fn block(&self, x: usize, y: usize) -> usize {
+        let nx = self.xs.len() - 1;
+        let bx = self.xs.partition_point(|&bound| bound <= x) - 1;
+        let by = self.ys.partition_point(|&bound| bound <= y) - 1;
+        by * nx + bx
+    }
+
+    pub fn light(&self, x: usize, y: usize) -> bool {
+        self.lights[self.block(x, y)]
+    }
+
+    pub fn dimmable(&self, x: usize, y: usize) -> u32 {
+        self.dimmables[self.block(x, y)]
+    }
+
+    pub fn nlit(&self) -> u64 {
+        self.areas()
+            .filter(|(idx, _)| self.lights[*idx])
+            .map(|(_, area)| area)
+            .sum()
+    }
+
+    pub fn brightness(&self) -> Result<u64, Overflow> {
+        total_brightness(self.areas().map(|(idx, area)| (area, self.dimmables[idx])))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INSTRUCTIONS: &str = "\
+        \x20turn on 0,0 through 999,999\n\
+        \x20toggle 0,0 through 999,0\n\
+        \x20turn off 499,499 through 500,500\n\
+        \x20toggle 250,100 through 750,900\n\
+        \x20turn off 0,0 through 10,999\n\
+        \x20turn on 5,5 through 5,5\n\
+        \x20toggle 300,200 through 999,400\
+        ";
+
+    #[test]
+    fn same_as_dense_grid() {
+        let instructions = parse_input(INSTRUCTIONS).unwrap();
+        let mut grid = Grid::new(1000, 1000).unwrap();
+        let mut compressed = CompressedGrid::new(1000, 1000, &instructions);
+        for instruction in &instructions {
+            process_instruction(&mut grid, instruction).unwrap();
+            compressed.process_instruction(instruction).unwrap();
+            assert_eq!(compressed.nlit(), grid.nlit() as u64);
+            assert_eq!(compressed.brightness(), grid.brightness());
+        }
+    }
+
+    #[test]
+    fn shapes_same_as_dense_grid() {
+        let instructions = parse_input(
+            "\
+            \x20toggle circle 40,30 r 25\n\
+            \x20set brightness 4 line 0,0 to 99,60\n\
+            \x20dim by 3 circle 50,50 r 10\n\
+            \x20invert all\n\
+            \x20turn off 10,10 through 90,20\
+            ",
+        )
+        .unwrap();
+        let mut grid = Grid::new(100, 70).unwrap();
+        let mut compressed = CompressedGrid::new(100, 70, &instructions);
+        for instruction in &instructions {
+            process_instruction(&mut grid, instruction).unwrap();
+            compressed.process_instruction(instruction).unwrap();
+            assert_eq!(compressed.nlit(), grid.nlit() as u64);
+            assert_eq!(compressed.brightness(), grid.brightness());
+        }
+    }
+
+    #[test]
+    fn brightness_overflow() {
+        let instructions =
+            parse_input("set brightness 4294967295 0,0 through 9,9\nturn on 5,5 through 5,5")
+                .unwrap();
+        let mut compressed = CompressedGrid::new(10, 10, &instructions);
+        compressed.process_instruction(&instructions[0]).unwrap();
+        assert_eq!(
+            compressed.process_instruction(&instructions[1]),
+            Err(Overflow::Light(u32::MAX, 1))
+        );
+    }
+
+    #[test]
+    fn block_count_independent_of_grid_size() {
+        let instructions = parse_input("toggle 10,10 through 19,19").unwrap();
+        let compressed = CompressedGrid::new(1000, 1000, &instructions);
+        assert_eq!(compressed.lights.len(), 9);
+    }
+
+    #[test]
+    fn huge_grid() {
+        let instructions = parse_input(
+            "\
+            \x20turn on 0,0 through 999999999,999999999\n\
+            \x20toggle 0,0 through 999999999,0\n\
+            \x20turn off 1,1 through 2,2\
+            ",
+        )
+        .unwrap();
+        let mut compressed = CompressedGrid::new(1_000_000_000, 1_000_000_000, &instructions);
+        for instruction in &instructions {
+            compressed.process_instruction(instruction).unwrap();
+        }
+        assert_eq!(
+            compressed.nlit(),
+            1_000_000_000_000_000_000 - 1_000_000_000 - 4
+        );
+        assert_eq!(
+            compressed.brightness(),
+            Ok(1_000_000_000_000_000_000 + 2 * 1_000_000_000 - 4)
+        );
+    }
+}
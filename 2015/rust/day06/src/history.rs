@@ -0,0 +1,177 @@
+/*
+ * Undo/replay log for the dense grid.
+ *
+ * Toggle can be undone by itself, but turning lights on or off and the clamped dimming lose
+ * the previous state. Instead of inverting instructions, the history keeps a copy of the grid
+ * every `interval` instructions. Going back restores the closest checkpoint at or before the
+ * target and replays the remaining instructions, so any position is at most `interval - 1`
+ * instructions away from a checkpoint.
+ */
+use super::*;
+
+pub struct History<'a> {
+    instructions: &'a [Instruction],
+    interval: usize,
+    // checkpoints[i] is the grid after i * interval instructions, created on the way forward
+    checkpoints: Vec<Grid>,
+    grid: Grid,
+    // number of instructions applied to grid
+    position: usize,
+}
+
+impl<'a> History<'a> {
+    pub fn new(
+        ncols: usize,
+        nrows: usize,
+        instructions: &'a [Instruction],
+        interval: usize,
+    ) -> Result<Self, TooLarge> {
+        let grid = Grid::new(ncols, nrows)?;
+        Ok(Self {
+            instructions,
+            interval: interval.max(1),
+            checkpoints: vec![grid.clone()],
+            grid,
+            position: 0,
+        })
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn step_forward(&mut self) -> Result<bool, Overflow> {
+        if self.position == self.instructions.len() {
+            return Ok(false);
+        }
+        process_instruction(&mut self.grid, &self.instructions[self.position])?;
+        self.position += 1;
+        if self.position.is_multiple_of(self.interval)
+            && self.checkpoints.len() == self.position / self.interval
+        {
+            self.checkpoints.push(self.grid.clone());
+        }
+        Ok(true)
+    }
+
+    pub fn step_back(&mut self) -> Result<bool, Overflow> {
+        if self.position == 0 {
+            return Ok(false);
+        }
+        self.seek(self.position - 1)?;
+        Ok(true)
+    }
+
+    // Moves to the state after the first `position` instructions
+    pub fn seek(&mut self, position: usize) -> Result<(), Overflow> {
+        let position = position.min(self.instructions.len());
+        if position < self.position {
+            let checkpoint = (position / self.interval).min(self.checkpoints.len() - 1);
+            self.grid = self.checkpoints[checkpoint].clone();
+            self.position = checkpoint * self.interval;
+        }
+        while self.position < position {
+            self.step_forward()?;
+        }
+        Ok(())
+    }
+
+    // Index of the last instruction before the current position that changed the light at (x, y),
+    // either switching it or changing its brightness
+    pub fn last_change(&self, x: usize, y: usize) -> Result<Option<usize>, Overflow> {
+        let (mut light, mut dimmable) = (false, 0);
+        let mut last = None;
+        for (idx, instruction) in self.instructions[..self.position].iter().enumerate() {
+            let (ncols, nrows) = (self.grid.ncols, self.grid.nrows);
+            if !instruction.shape.contains(x, y, ncols, nrows) {
+                continue;
+            }
+            let before = (light, dimmable);
+            rules::apply(&Switch, instruction.typ, &mut light)?;
+            rules::apply(&Dimmer, instruction.typ, &mut dimmable)?;
+            if (light, dimmable) != before {
+                last = Some(idx);
+            }
+        }
+        Ok(last)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INSTRUCTIONS: &str = "\
+        \x20turn on 0,0 through 7,4\n\
+        \x20toggle 2,1 through 5,3\n\
+        \x20turn off 0,0 through 0,4\n\
+        \x20turn off 0,0 through 0,4\n\
+        \x20toggle 0,0 through 7,0\n\
+        \x20turn on 3,2 through 3,2\n\
+        \x20toggle 6,0 through 7,4\
+        ";
+
+    fn state(grid: &Grid) -> (usize, Vec<u32>) {
+        (grid.nlit(), grid.dimmables.clone())
+    }
+
+    fn states(instructions: &[Instruction]) -> Vec<(usize, Vec<u32>)> {
+        let mut grid = Grid::new(8, 5).unwrap();
+        let mut states = vec![state(&grid)];
+        for instruction in instructions {
+            process_instruction(&mut grid, instruction).unwrap();
+            states.push(state(&grid));
+        }
+        states
+    }
+
+    #[test]
+    fn step_forward_and_back() {
+        let instructions = parse_input(INSTRUCTIONS).unwrap();
+        let expected = states(&instructions);
+        let mut history = History::new(8, 5, &instructions, 3).unwrap();
+        while history.step_forward().unwrap() {
+            assert_eq!(state(history.grid()), expected[history.position()]);
+        }
+        assert_eq!(history.position(), 7);
+        while history.step_back().unwrap() {
+            assert_eq!(state(history.grid()), expected[history.position()]);
+        }
+        assert_eq!(history.position(), 0);
+    }
+
+    #[test]
+    fn seek() {
+        let instructions = parse_input(INSTRUCTIONS).unwrap();
+        let expected = states(&instructions);
+        let mut history = History::new(8, 5, &instructions, 2).unwrap();
+        for position in [5, 1, 7, 4, 0, 6, 6, 3, 100].iter() {
+            history.seek(*position).unwrap();
+            let position = (*position).min(7);
+            assert_eq!(history.position(), position);
+            assert_eq!(state(history.grid()), expected[position]);
+        }
+        assert_eq!(history.checkpoints.len(), 4);
+    }
+
+    #[test]
+    fn last_change() {
+        let instructions = parse_input(INSTRUCTIONS).unwrap();
+        let mut history = History::new(8, 5, &instructions, 2).unwrap();
+        history.seek(7).unwrap();
+        // the second turn off is a no-op for an already dark light
+        assert_eq!(history.last_change(0, 3), Ok(Some(2)));
+        assert_eq!(history.last_change(3, 2), Ok(Some(5)));
+        assert_eq!(history.last_change(7, 4), Ok(Some(6)));
+        assert_eq!(history.last_change(1, 1), Ok(Some(0)));
+
+        history.seek(5).unwrap();
+        assert_eq!(history.last_change(3, 2), Ok(Some(1)));
+        history.seek(0).unwrap();
+        assert_eq!(history.last_change(3, 2), Ok(None));
+    }
+}
@@ -0,0 +1,140 @@
+/*
+ * Bit packed storage of the lights, one bit per light instead of one byte.
+ *
+ * Each row starts at a new word, so a span of a row never crosses into the next row and can be
+ * updated with one mask operation per word it touches.
+ */
+const WORD_BITS: usize = 64;
+
+#[derive(Clone)]
+pub struct Lights {
+    words: Vec<u64>,
+    words_per_row: usize,
+}
+
+// Mask with the bits from..=through of a word set
+fn mask(from: usize, through: usize) -> u64 {
+    let upper = if through == WORD_BITS - 1 {
+        u64::MAX
+    } else {
+        (1 << (through + 1)) - 1
+    };
+    upper & !((1 << from) - 1)
+}
+
+impl Lights {
+    pub fn new(ncols: usize, nrows: usize) -> Self {
+        let words_per_row = ncols.div_ceil(WORD_BITS);
+        Self {
+            words: vec![0; words_per_row * nrows],
+            words_per_row,
+        }
+    }
+
+    pub fn get(&self, col: usize, row: usize) -> bool {
+        let word = row * self.words_per_row + col / WORD_BITS;
+        self.words[word] & (1 << (col % WORD_BITS)) != 0
+    }
+
+    // Calls `f` with each word and the mask covering the columns from..=through of the row
+    fn span(&mut self, row: usize, from: usize, through: usize, f: impl Fn(&mut u64, u64)) {
+        let row_start = row * self.words_per_row;
+        let (first, last) = (from / WORD_BITS, through / WORD_BITS);
+        for word in first..=last {
+            let lo = if word == first { from % WORD_BITS } else { 0 };
+            let hi = if word == last {
+                through % WORD_BITS
+            } else {
+                WORD_BITS - 1
+            };
+            f(&mut self.words[row_start + word], mask(lo, hi));
+        }
+    }
+
+    pub fn on_span(&mut self, row: usize, from: usize, through: usize) {
+        self.span(row, from, through, |word, mask| *word |= mask);
+    }
+
+    pub fn off_span(&mut self, row: usize, from: usize, through: usize) {
+        self.span(row, from, through, |word, mask| *word &= !mask);
+    }
+
+    pub fn toggle_span(&mut self, row: usize, from: usize, through: usize) {
+        self.span(row, from, through, |word, mask| *word ^= mask);
+    }
+
+    // Moves the rows from `row` onwards into a new instance
+    pub fn split_off_rows(&mut self, row: usize) -> Lights {
+        Self {
+            words: self.words.split_off(row * self.words_per_row),
+            words_per_row: self.words_per_row,
+        }
+    }
+
+    // Adds the rows of `other` below the rows of self, both need the same number of columns
+    pub fn append_rows(&mut self, mut other: Lights) {
+        assert_eq!(self.words_per_row, other.words_per_row);
+        self.words.append(&mut other.words);
+    }
+
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|x| x.count_ones() as usize).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn masks() {
+        assert_eq!(mask(0, 0), 1);
+        assert_eq!(mask(0, 63), u64::MAX);
+        assert_eq!(mask(1, 3), 0b1110);
+        assert_eq!(mask(63, 63), 1 << 63);
+    }
+
+    #[test]
+    fn spans_across_words() {
+        let mut lights = Lights::new(200, 3);
+        lights.on_span(1, 60, 130);
+        assert_eq!(lights.count(), 71);
+        assert!(!lights.get(59, 1));
+        assert!(lights.get(60, 1));
+        assert!(lights.get(130, 1));
+        assert!(!lights.get(131, 1));
+        assert!(!lights.get(60, 0) && !lights.get(60, 2));
+
+        lights.toggle_span(1, 0, 199);
+        assert_eq!(lights.count(), 200 - 71);
+        lights.off_span(1, 0, 63);
+        assert_eq!(lights.count(), 200 - 71 - 60);
+    }
+
+    #[test]
+    fn split_and_append_rows() {
+        let mut lights = Lights::new(100, 4);
+        lights.on_span(0, 0, 99);
+        lights.on_span(3, 10, 19);
+        let mut bottom = lights.split_off_rows(2);
+        assert_eq!((lights.count(), bottom.count()), (100, 10));
+        assert!(bottom.get(10, 1));
+
+        bottom.toggle_span(0, 0, 0);
+        lights.append_rows(bottom);
+        assert_eq!(lights.count(), 111);
+        assert!(lights.get(0, 2) && lights.get(19, 3));
+    }
+
+    #[test]
+    fn single_lights() {
+        let mut lights = Lights::new(70, 2);
+        lights.on_span(1, 69, 69);
+        lights.toggle_span(0, 0, 0);
+        assert_eq!(lights.count(), 2);
+        assert!(lights.get(69, 1) && lights.get(0, 0));
+        lights.off_span(1, 69, 69);
+        lights.toggle_span(0, 0, 0);
+        assert_eq!(lights.count(), 0);
+    }
+}
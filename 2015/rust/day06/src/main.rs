@@ -1,161 +1,796 @@
+use std::{
+    env, fmt, fs,
+    io::{self, Read},
+    process,
+};
+
+use animate::Animation;
+use compressed::CompressedGrid;
+use history::History;
+use lights::Lights;
+use parallel::process_parallel;
 use regex::Regex;
+use render::{render, render_against, Image, Palette, Raster};
+use rules::{CappedDimmer, Cycle, Dimmer, LightRule, RuleGrid, Switch};
+use segtree::SegTreeGrid;
+use shapes::{Shape, Span};
+use solve::{solve, Bitmap};
+use sparse::SparseGrid;
+use stats::Report;
 
 use InstructionType::*;
 
+mod animate;
+mod compressed;
+mod history;
+mod lights;
+mod parallel;
+mod render;
+mod rules;
+mod segtree;
+mod shapes;
+mod snapshot;
+mod solve;
+mod sparse;
+mod stats;
+
 type Coord = (usize, usize);
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 struct Locations {
     from: Coord,
     through: Coord,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum InstructionType {
     TurnOn,
     TurnOff,
     Toggle,
+    // a brightness of 0 switches lights off, everything else switches them on
+    SetBrightness(u32),
+    // generalizes turning off, which dims by 1
+    DimBy(u32),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 struct Instruction {
-    locations: Locations,
+    shape: Shape,
     typ: InstructionType,
 }
 
+#[derive(Debug, PartialEq)]
+enum ParseErrorKind {
+    Malformed,
+    UnknownInstruction(String),
+    InvalidNumber(String),
+    // from is not <= through
+    Reversed,
+    OutOfBounds(Coord),
+    // a '}' without a matching 'repeat K {'
+    UnexpectedClose,
+    UnclosedRepeat,
+    // the repeats expand to more than MAX_INSTRUCTIONS
+    TooManyInstructions,
+}
+
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    // 1 based
+    line: usize,
+    text: String,
+    kind: ParseErrorKind,
+}
+
+impl fmt::Display for InstructionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TurnOn => write!(f, "turn on"),
+            TurnOff => write!(f, "turn off"),
+            Toggle => write!(f, "toggle"),
+            SetBrightness(n) => write!(f, "set brightness {}", n),
+            DimBy(n) => write!(f, "dim by {}", n),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.typ, self.shape)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ParseErrorKind::Malformed => write!(
+                f,
+                "expected '<instruction> <shape>' with a shape of 'X,Y through X,Y', \
+                 'circle X,Y r R', 'line X,Y to X,Y' or 'all' but got '{}'",
+                self.text
+            ),
+            ParseErrorKind::UnknownInstruction(typ) => write!(f, "unknown instruction '{}'", typ),
+            ParseErrorKind::InvalidNumber(number) => write!(f, "invalid coordinate '{}'", number),
+            ParseErrorKind::Reversed => write!(
+                f,
+                "from needs to be less than or equal to through in '{}'",
+                self.text
+            ),
+            ParseErrorKind::OutOfBounds((x, y)) => {
+                write!(f, "{},{} lies outside of the grid in '{}'", x, y, self.text)
+            }
+            ParseErrorKind::UnexpectedClose => write!(f, "'}}' without a matching repeat"),
+            ParseErrorKind::UnclosedRepeat => write!(f, "'{}' is never closed", self.text),
+            ParseErrorKind::TooManyInstructions => write!(
+                f,
+                "'{}' expands to more than {} instructions",
+                self.text, MAX_INSTRUCTIONS
+            ),
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(x: &str) -> Result<T, ParseErrorKind> {
+    x.parse()
+        .map_err(|_| ParseErrorKind::InvalidNumber(x.to_string()))
+}
+
+impl InstructionType {
+    // "turn on", "turn off", "toggle" or its alias "invert", "set brightness N" and "dim by N"
+    fn from_verb(verb: &str) -> Result<InstructionType, ParseErrorKind> {
+        match verb {
+            "turn on" => Ok(TurnOn),
+            "turn off" => Ok(TurnOff),
+            "toggle" | "invert" => Ok(Toggle),
+            _ => {
+                if let Some(n) = verb.strip_prefix("set brightness ") {
+                    Ok(SetBrightness(parse_number(n)?))
+                } else if let Some(n) = verb.strip_prefix("dim by ") {
+                    Ok(DimBy(parse_number(n)?))
+                } else {
+                    Err(ParseErrorKind::UnknownInstruction(verb.to_string()))
+                }
+            }
+        }
+    }
+}
+
 impl Instruction {
-    fn from_strings(
-        typ: &str,
-        from_x: &str,
-        from_y: &str,
-        through_x: &str,
-        through_y: &str,
-    ) -> Instruction {
-        let locations = {
-            let from = (
-                from_x.parse().expect("invalid from_x"),
-                from_y.parse().expect("invalid from_y"),
-            );
-            let through = (
-                through_x.parse().expect("invalid through_x"),
-                through_y.parse().expect("invalid through_y"),
-            );
-            Locations { from, through }
+    // Group 1 is the verb, followed by the groups of exactly one shape
+    const PATTERN: &'static str = concat!(
+        r"^(.+?) (?:",
+        r"(\S+),(\S+) through (\S+),(\S+)",
+        r"|circle (\S+),(\S+) r (\S+)",
+        r"|line (\S+),(\S+) to (\S+),(\S+)",
+        r"|(all))$"
+    );
+
+    fn from_captures(captures: &regex::Captures) -> Result<Instruction, ParseErrorKind> {
+        let number = |idx: usize| parse_number::<usize>(&captures[idx]);
+        let coord = |idx: usize| Ok((number(idx)?, number(idx + 1)?));
+        let shape = if captures.get(2).is_some() {
+            let (from, through) = (coord(2)?, coord(4)?);
+            if from.0 > through.0 || from.1 > through.1 {
+                return Err(ParseErrorKind::Reversed);
+            }
+            Shape::Rect(Locations { from, through })
+        } else if captures.get(6).is_some() {
+            Shape::Circle {
+                center: coord(6)?,
+                radius: number(8)?,
+            }
+        } else if captures.get(9).is_some() {
+            Shape::Line {
+                from: coord(9)?,
+                to: coord(11)?,
+            }
+        } else {
+            Shape::All
         };
+        Ok(Instruction {
+            typ: InstructionType::from_verb(&captures[1])?,
+            shape,
+        })
+    }
+}
 
-        match typ {
-            "turn on" => Instruction {
-                locations,
-                typ: TurnOn,
-            },
-            "turn off" => Instruction {
-                locations,
-                typ: TurnOff,
-            },
-            "toggle" => Instruction {
-                locations,
-                typ: Toggle,
-            },
-            _ => panic!("Unknown instruction {}", &typ),
+#[derive(Debug, PartialEq)]
+enum Overflow {
+    // (brightness, by) of a light whose brightness would exceed u32::MAX
+    Light(u32, u32),
+    // the total brightness exceeds u64::MAX
+    Total,
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Overflow::Light(brightness, by) => write!(
+                f,
+                "brightness overflow: {} + {} exceeds the maximum of {}",
+                brightness,
+                by,
+                u32::MAX
+            ),
+            Overflow::Total => write!(f, "total brightness overflows u64"),
         }
     }
 }
 
+// A grid of (ncols, nrows) whose cells can't be allocated
+#[derive(Debug, PartialEq)]
+struct TooLarge(usize, usize);
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a {}x{} grid is too large", self.0, self.1)
+    }
+}
+
+// Allocates `len` copies of `value` for a grid of `size`, `None` if the length overflowed.
+// Fails instead of aborting when there isn't enough memory.
+fn allocate<T: Clone>(len: Option<usize>, value: T, size: Coord) -> Result<Vec<T>, TooLarge> {
+    let len = len.ok_or(TooLarge(size.0, size.1))?;
+    let mut cells = Vec::new();
+    cells
+        .try_reserve_exact(len)
+        .map_err(|_| TooLarge(size.0, size.1))?;
+    cells.resize(len, value);
+    Ok(cells)
+}
+
+// Checked increase of the brightness of a single light, which is stored in a u32
+fn brighten(brightness: u32, by: u32) -> Result<u32, Overflow> {
+    brightness
+        .checked_add(by)
+        .ok_or(Overflow::Light(brightness, by))
+}
+
+// Total brightness of (number of lights, brightness) pairs, checked as huge grids could exceed u64
+fn total_brightness(mut lights: impl Iterator<Item = (u64, u32)>) -> Result<u64, Overflow> {
+    lights
+        .try_fold(0u64, |total, (n, brightness)| {
+            total.checked_add(n.checked_mul(brightness as u64)?)
+        })
+        .ok_or(Overflow::Total)
+}
+
+#[derive(Clone)]
 struct Grid {
-    lights: Vec<bool>,
+    lights: Lights,
     dimmables: Vec<u32>,
     ncols: usize,
+    nrows: usize,
 }
 
 impl Grid {
-    fn new(ncols: usize, nrows: usize) -> Self {
-        let ncells = ncols * nrows;
-        let lights = vec![false; ncells];
-        let dimmables = vec![0; ncells];
-        Self {
+    fn new(ncols: usize, nrows: usize) -> Result<Self, TooLarge> {
+        let dimmables = allocate(ncols.checked_mul(nrows), 0, (ncols, nrows))?;
+        let lights = Lights::new(ncols, nrows);
+        Ok(Self {
             lights,
             dimmables,
             ncols,
-        }
+            nrows,
+        })
     }
 
     fn idx(&self, col: usize, row: usize) -> usize {
         row * self.ncols + col
     }
 
-    fn on(&mut self, col: usize, row: usize) {
-        let idx = self.idx(col, row);
-        self.lights[idx] = true;
-        self.dimmables[idx] += 1;
+    // Applies an instruction of type `typ` to the columns from..=through of a row. The lights
+    // follow the Switch rule and the brightness the Dimmer rule.
+    fn apply_span(
+        &mut self,
+        typ: InstructionType,
+        (row, from, through): Span,
+    ) -> Result<(), Overflow> {
+        // all four transitions of a light are mask operations
+        match Switch::transition(typ) {
+            (true, true) => self.lights.on_span(row, from, through),
+            (false, false) => self.lights.off_span(row, from, through),
+            (true, false) => self.lights.toggle_span(row, from, through),
+            (false, true) => {}
+        }
+        let (from, through) = (self.idx(from, row), self.idx(through, row));
+        rules::apply_span(&Dimmer, typ, &mut self.dimmables[from..=through])
     }
 
-    fn off(&mut self, col: usize, row: usize) {
-        let idx = self.idx(col, row);
-        self.lights[idx] = false;
-        self.dimmables[idx] -= if self.dimmables[idx] == 0 { 0 } else { 1 };
+    // Moves the rows from `row` onwards into a new grid
+    fn split_off_rows(&mut self, row: usize) -> Grid {
+        let nrows = self.nrows - row;
+        self.nrows = row;
+        Self {
+            lights: self.lights.split_off_rows(row),
+            dimmables: self.dimmables.split_off(row * self.ncols),
+            ncols: self.ncols,
+            nrows,
+        }
     }
 
-    fn toggle(&mut self, col: usize, row: usize) {
-        let idx = self.idx(col, row);
-        self.lights[idx] = !self.lights[idx];
-        self.dimmables[idx] += 2;
+    // Adds the rows of `other` below the rows of self
+    fn append_rows(&mut self, mut other: Grid) {
+        assert_eq!(self.ncols, other.ncols);
+        self.lights.append_rows(other.lights);
+        self.dimmables.append(&mut other.dimmables);
+        self.nrows += other.nrows;
     }
 
     fn nlit(&self) -> usize {
-        self.lights.iter().filter(|&x| *x).count()
+        self.lights.count()
     }
 
-    fn brightness(&self) -> u32 {
-        self.dimmables.iter().sum()
+    fn brightness(&self) -> Result<u64, Overflow> {
+        total_brightness(self.dimmables.iter().map(|x| (1, *x)))
     }
 }
 
-fn process_instruction(grid: &mut Grid, instruction: &Instruction) {
-    let Instruction {
-        typ,
-        locations: Locations { from, through },
-    } = instruction;
-    for row in from.0..=through.0 {
-        for col in from.1..=through.1 {
-            match typ {
-                TurnOn => grid.on(col, row),
-                TurnOff => grid.off(col, row),
-                Toggle => grid.toggle(col, row),
-            }
-        }
+fn process_instruction(grid: &mut Grid, instruction: &Instruction) -> Result<(), Overflow> {
+    for span in instruction.shape.spans(grid.ncols, grid.nrows) {
+        grid.apply_span(instruction.typ, span)?;
     }
+    Ok(())
 }
 
-fn parse_input(input: &str) -> Vec<Instruction> {
-    let rx = Regex::new(r"(turn on|turn off|toggle) (\d+),(\d+) through (\d+),(\d+)").unwrap();
-    rx.captures_iter(input)
-        .filter_map(|x| {
-            let groups = (x.get(1), x.get(2), x.get(3), x.get(4), x.get(5));
-            match groups {
-                (Some(typ), Some(from_x), Some(from_y), Some(through_x), Some(through_y)) => {
-                    Some(Instruction::from_strings(
-                        typ.as_str(),
-                        from_x.as_str(),
-                        from_y.as_str(),
-                        through_x.as_str(),
-                        through_y.as_str(),
-                    ))
+// Upper bound for the instructions of an input after expanding its repeats
+const MAX_INSTRUCTIONS: usize = 1_000_000;
+
+// Parses one instruction per line, empty lines are ignored. The lines between 'repeat K {' and
+// '}' are repeated K times, repeats may be nested.
+// When `bounds` (ncols, nrows) are given all coordinates need to lie inside of them.
+fn parse_lines(
+    input: &str,
+    bounds: Option<(usize, usize)>,
+) -> Result<Vec<Instruction>, ParseError> {
+    let rx = Regex::new(Instruction::PATTERN).unwrap();
+    let repeat_rx = Regex::new(r"^repeat (\S+) \{$").unwrap();
+    // the open repeats as (line, text, count, instructions so far)
+    let mut blocks = vec![(0, String::new(), 1, vec![])];
+    for (idx, line) in input.lines().enumerate() {
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+        let error = |kind| ParseError {
+            line: idx + 1,
+            text: text.to_string(),
+            kind,
+        };
+        if let Some(captures) = repeat_rx.captures(text) {
+            let count = parse_number::<usize>(&captures[1]).map_err(error)?;
+            blocks.push((idx + 1, text.to_string(), count, vec![]));
+            continue;
+        }
+        if text == "}" {
+            if blocks.len() == 1 {
+                return Err(error(ParseErrorKind::UnexpectedClose));
+            }
+            let (line, text, count, block) = blocks.pop().unwrap();
+            let outer = &mut blocks.last_mut().unwrap().3;
+            let total = block
+                .len()
+                .checked_mul(count)
+                .and_then(|x| x.checked_add(outer.len()))
+                .filter(|&x| x <= MAX_INSTRUCTIONS);
+            if total.is_none() {
+                return Err(ParseError {
+                    line,
+                    text,
+                    kind: ParseErrorKind::TooManyInstructions,
+                });
+            }
+            for _ in 0..count {
+                outer.extend(block.iter().cloned());
+            }
+            continue;
+        }
+        let captures = rx
+            .captures(text)
+            .ok_or_else(|| error(ParseErrorKind::Malformed))?;
+        let instruction = Instruction::from_captures(&captures).map_err(error)?;
+        if let Some((ncols, nrows)) = bounds {
+            for coord in instruction.shape.coords() {
+                if coord.0 >= ncols || coord.1 >= nrows {
+                    return Err(error(ParseErrorKind::OutOfBounds(coord)));
                 }
-                _ => None,
             }
+        }
+        blocks.last_mut().unwrap().3.push(instruction);
+    }
+    let (line, text, _, instructions) = blocks.pop().unwrap();
+    if !blocks.is_empty() {
+        return Err(ParseError {
+            line,
+            text,
+            kind: ParseErrorKind::UnclosedRepeat,
+        });
+    }
+    Ok(instructions)
+}
+
+fn parse_input(input: &str) -> Result<Vec<Instruction>, ParseError> {
+    parse_lines(input, None)
+}
+
+fn parse_input_for_grid(
+    input: &str,
+    ncols: usize,
+    nrows: usize,
+) -> Result<Vec<Instruction>, ParseError> {
+    parse_lines(input, Some((ncols, nrows)))
+}
+
+// The smallest grid (ncols, nrows) that contains all instructions
+fn infer_size(instructions: &[Instruction]) -> (usize, usize) {
+    instructions
+        .iter()
+        .filter_map(|x| x.shape.extent())
+        .fold((1, 1), |(ncols, nrows), through| {
+            (
+                ncols.max(through.0.saturating_add(1)),
+                nrows.max(through.1.saturating_add(1)),
+            )
         })
-        .collect()
+}
+
+// Parses "WxH" into (ncols, nrows)
+fn parse_size(size: &str) -> Option<(usize, usize)> {
+    let mut parts = size.trim().splitn(2, 'x');
+    let ncols = parts.next()?.parse().ok()?;
+    let nrows = parts.next()?.parse().ok()?;
+    if ncols == 0 || nrows == 0 {
+        return None;
+    }
+    Some((ncols, nrows))
+}
+
+fn parse_coord(coord: &str) -> Option<Coord> {
+    let mut parts = coord.trim().splitn(2, ',');
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+fn parse_locations(locations: &str) -> Option<Locations> {
+    let rx = Regex::new(r"^(\d+),(\d+) through (\d+),(\d+)$").unwrap();
+    let captures = rx.captures(locations.trim())?;
+    let coord = |idx: usize| captures[idx].parse::<usize>().ok();
+    Some(Locations {
+        from: (coord(1)?, coord(2)?),
+        through: (coord(3)?, coord(4)?),
+    })
+}
+
+// Checks that `locations` are ordered and lie on a grid of `ncols` x `nrows`
+fn check_locations(locations: &Locations, ncols: usize, nrows: usize) -> Result<(), String> {
+    let Locations { from, through } = locations;
+    if from.0 > through.0 || from.1 > through.1 {
+        return Err(format!(
+            "{},{} needs to be less than or equal to {},{}",
+            from.0, from.1, through.0, through.1
+        ));
+    }
+    if through.0 >= ncols || through.1 >= nrows {
+        return Err(format!(
+            "{},{} lies outside of the {}x{} grid",
+            through.0, through.1, ncols, nrows
+        ));
+    }
+    Ok(())
+}
+
+fn arg(name: &str) -> Option<String> {
+    env::args().skip_while(|x| x != name).nth(1)
+}
+
+// The count given for `name`, reports anything else the same way as other invalid input
+fn count_arg(name: &str) -> Option<usize> {
+    arg(name).map(|count| {
+        count.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid {} '{}', expected a number", name, count);
+            process::exit(1);
+        })
+    })
+}
+
+fn palette_arg() -> Palette {
+    let palette = arg("--palette").unwrap_or_else(|| "lights".to_string());
+    Palette::from_name(&palette).unwrap_or_else(|| {
+        eprintln!("Unknown palette '{}', use lights, gray or heat", palette);
+        process::exit(1);
+    })
+}
+
+fn scale_arg() -> usize {
+    count_arg("--scale").unwrap_or(1)
+}
+
+fn save_render(raster: &impl Raster, path: &str) {
+    if let Err(err) = render(raster, palette_arg(), scale_arg()).save(path) {
+        eprintln!("Unable to write '{}': {}", path, err);
+        process::exit(1);
+    }
+}
+
+fn run_rule<R: LightRule>(
+    rule: R,
+    size: (usize, usize),
+    instructions: &[Instruction],
+) -> Result<u64, String> {
+    let mut grid = RuleGrid::new(rule, size.0, size.1).map_err(|err| err.to_string())?;
+    for instruction in instructions {
+        grid.process_instruction(instruction)
+            .map_err(|err| err.to_string())?;
+    }
+    grid.aggregate().map_err(|err| err.to_string())
+}
+
+// Reports an overflowing brightness or a grid that is too large and exits, the same way as
+// invalid input
+fn exit_on_error<T, E: fmt::Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    })
+}
+
+// Reads the instructions from a file or from stdin for '-'
+fn read_input(path: &str) -> io::Result<String> {
+    if path == "-" {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        return Ok(input);
+    }
+    fs::read_to_string(path)
 }
 
 fn main() {
-    let input = include_str!("input.txt");
-    let mut grid = Grid::new(1000, 1000);
-    let input = parse_input(input);
-    for instruction in input {
-        process_instruction(&mut grid, &instruction);
+    let input = match arg("--input") {
+        Some(path) => read_input(&path).unwrap_or_else(|err| {
+            eprintln!("Unable to read '{}': {}", path, err);
+            process::exit(1);
+        }),
+        None => include_str!("input.txt").to_string(),
+    };
+    // Without --size the grid is just large enough for all instructions
+    let size = arg("--size").map(|size| {
+        parse_size(&size).unwrap_or_else(|| {
+            eprintln!("Invalid size '{}', expected 'WxH'", size);
+            process::exit(1);
+        })
+    });
+    let input = match size {
+        Some((ncols, nrows)) => parse_input_for_grid(&input, ncols, nrows),
+        None => parse_input(&input),
+    }
+    .unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    let (ncols, nrows) = size.unwrap_or_else(|| infer_size(&input));
+
+    if arg("--at").is_some() || arg("--blame").is_some() {
+        let at = count_arg("--at").unwrap_or(input.len());
+        let mut history = exit_on_error(History::new(ncols, nrows, &input, 50));
+        exit_on_error(history.seek(at));
+        let grid = history.grid();
+        println!(
+            "after {} instructions: {} lights on, brightness {}",
+            history.position(),
+            grid.nlit(),
+            exit_on_error(grid.brightness())
+        );
+        if let Some(light) = arg("--blame") {
+            let (x, y) = parse_coord(&light).unwrap_or_else(|| {
+                eprintln!("Invalid light '{}', expected 'X,Y'", light);
+                process::exit(1);
+            });
+            match exit_on_error(history.last_change(x, y)) {
+                Some(idx) => {
+                    println!(
+                        "{},{} was last changed by instruction {}: {}",
+                        x,
+                        y,
+                        idx + 1,
+                        input[idx]
+                    );
+                    let light =
+                        |grid: &Grid| (grid.lights.get(x, y), grid.dimmables[grid.idx(x, y)]);
+                    exit_on_error(history.seek(idx + 1));
+                    let after = light(history.grid());
+                    exit_on_error(history.step_back());
+                    let before = light(history.grid());
+                    println!(
+                        "on: {} -> {}, brightness: {} -> {}",
+                        before.0, after.0, before.1, after.1
+                    );
+                }
+                None => println!("{},{} was never changed", x, y),
+            }
+        }
+        return;
+    }
+
+    if let Some(query) = arg("--query") {
+        let locations = parse_locations(&query).unwrap_or_else(|| {
+            eprintln!("Invalid query '{}', expected 'X,Y through X,Y'", query);
+            process::exit(1);
+        });
+        if let Err(err) = check_locations(&locations, ncols, nrows) {
+            eprintln!("Invalid query '{}': {}", query, err);
+            process::exit(1);
+        }
+        let after = count_arg("--after").unwrap_or(input.len());
+        let mut grid = exit_on_error(SegTreeGrid::new(ncols, nrows));
+        for instruction in input.iter().take(after) {
+            exit_on_error(grid.process_instruction(instruction));
+        }
+        println!("lights on in {}: {}", query, grid.nlit_in(&locations));
+        println!(
+            "brightness in {}: {}",
+            query,
+            grid.brightness_in(&locations)
+        );
+        return;
+    }
+
+    if let Some(rule) = arg("--rule") {
+        let mut parts = rule.splitn(2, ':');
+        let (name, param) = (parts.next(), parts.next().map(|x| x.parse::<u32>()));
+        let total = match (name, param) {
+            (Some("switch"), None) => run_rule(Switch, (ncols, nrows), &input),
+            (Some("dimmer"), None) => run_rule(Dimmer, (ncols, nrows), &input),
+            (Some("cap"), Some(Ok(max))) => run_rule(CappedDimmer { max }, (ncols, nrows), &input),
+            (Some("cycle"), Some(Ok(n))) if n > 0 => run_rule(Cycle { n }, (ncols, nrows), &input),
+            _ => {
+                eprintln!(
+                    "Unknown rule '{}', use switch, dimmer, cap:<max> or cycle:<n>",
+                    rule
+                );
+                process::exit(1);
+            }
+        };
+        println!("{}: {}", rule, exit_on_error(total));
+        return;
+    }
+
+    if env::args().any(|x| x == "--report") {
+        let mut grid = exit_on_error(Grid::new(ncols, nrows));
+        let report = exit_on_error(Report::new(&mut grid, &input));
+        print!("{}", report.format(&input));
+        if let Some(path) = arg("--heatmap") {
+            save_render(&report.touches, &path);
+        }
+        return;
     }
-    println!("part 1 lights on: {}", grid.nlit());
-    println!("part 2 brightness: {}", grid.brightness());
+
+    // Prints instructions which draw the lit pixels of a PPM image
+    if let Some(path) = arg("--solve") {
+        let image = Image::load(&path).unwrap_or_else(|err| {
+            eprintln!("Unable to read '{}': {}", path, err);
+            process::exit(1);
+        });
+        for instruction in solve(&Bitmap::from_image(&image)) {
+            println!("{}", instruction);
+        }
+        return;
+    }
+
+    if let Some(dir) = arg("--animate") {
+        let crop = arg("--crop").map(|crop| {
+            let locations = parse_locations(&crop).unwrap_or_else(|| {
+                eprintln!("Invalid crop '{}', expected 'X,Y through X,Y'", crop);
+                process::exit(1);
+            });
+            if let Err(err) = check_locations(&locations, ncols, nrows) {
+                eprintln!("Invalid crop '{}': {}", crop, err);
+                process::exit(1);
+            }
+            locations
+        });
+        let animation = Animation {
+            stride: count_arg("--stride").unwrap_or(1),
+            crop,
+            palette: palette_arg(),
+            scale: scale_arg(),
+        };
+        let mut grid = exit_on_error(Grid::new(ncols, nrows));
+        match animation.save(&mut grid, &input, &dir) {
+            Ok(nframes) => println!("wrote {} frames to {}", nframes, dir),
+            Err(err) => {
+                eprintln!("Unable to write frames to '{}': {}", dir, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let engine = arg("--engine").unwrap_or_else(|| "dense".to_string());
+    let render_path = arg("--render");
+
+    let (nlit, brightness) = match engine.as_str() {
+        "dense" => {
+            // --resume continues from a snapshot, --after stops after that many instructions
+            let (mut grid, position) = match arg("--resume") {
+                Some(path) => Grid::load(&path).unwrap_or_else(|err| {
+                    eprintln!("Unable to load snapshot '{}': {}", path, err);
+                    process::exit(1);
+                }),
+                None => (exit_on_error(Grid::new(ncols, nrows)), 0),
+            };
+            let end = count_arg("--after").unwrap_or(input.len());
+            let instructions = &input[position.min(end)..end.min(input.len())];
+            match count_arg("--threads") {
+                Some(nthreads) => {
+                    exit_on_error(process_parallel(&mut grid, instructions, nthreads));
+                }
+                None => {
+                    for instruction in instructions {
+                        exit_on_error(process_instruction(&mut grid, instruction));
+                    }
+                }
+            }
+            if let Some(path) = &render_path {
+                save_render(&grid, path);
+            }
+            if let Some(path) = arg("--snapshot") {
+                let position = position.max(end.min(input.len()));
+                if let Err(err) = grid.save(&path, position) {
+                    eprintln!("Unable to write snapshot '{}': {}", path, err);
+                    process::exit(1);
+                }
+            }
+            if let Some(path) = arg("--compare") {
+                match Grid::load(&path) {
+                    Ok((other, _)) if (other.ncols, other.nrows) == (grid.ncols, grid.nrows) => {
+                        println!("compared to {}: {}", path, grid.diff(&other))
+                    }
+                    Ok(_) => println!("{} has a different size", path),
+                    Err(err) => {
+                        eprintln!("Unable to load snapshot '{}': {}", path, err);
+                        process::exit(1);
+                    }
+                }
+            }
+            (grid.nlit() as u64, exit_on_error(grid.brightness()))
+        }
+        "compressed" => {
+            let mut grid = CompressedGrid::new(ncols, nrows, &input);
+            for instruction in &input {
+                exit_on_error(grid.process_instruction(instruction));
+            }
+            if let Some(path) = &render_path {
+                save_render(&grid, path);
+            }
+            (grid.nlit(), exit_on_error(grid.brightness()))
+        }
+        "sparse" => {
+            let mut grid = SparseGrid::new(ncols, nrows);
+            for instruction in &input {
+                exit_on_error(grid.process_instruction(instruction));
+            }
+            if let Some(path) = &render_path {
+                save_render(&grid, path);
+            }
+            (grid.nlit(), exit_on_error(grid.brightness()))
+        }
+        "segtree" => {
+            let mut grid = exit_on_error(SegTreeGrid::new(ncols, nrows));
+            for instruction in &input {
+                exit_on_error(grid.process_instruction(instruction));
+            }
+            (grid.nlit(), exit_on_error(grid.brightness()))
+        }
+        _ => {
+            eprintln!(
+                "Unknown engine '{}', use dense, compressed, sparse or segtree",
+                engine
+            );
+            process::exit(1);
+        }
+    };
+    println!("part 1 lights on: {}", nlit);
+    println!("part 2 brightness: {}", brightness);
 }
 
 #[cfg(test)]
@@ -170,68 +805,360 @@ mod test {
             \x20turn off 539,243 through 559,965\n\
             \x20toggle 720,196 through 897,994\
             ",
-            ),
+            )
+            .unwrap(),
             vec![
                 Instruction {
                     typ: TurnOn,
-                    locations: Locations {
+                    shape: Shape::Rect(Locations {
                         from: (454, 398),
                         through: (844, 448)
-                    }
+                    })
                 },
                 Instruction {
                     typ: TurnOff,
-                    locations: Locations {
+                    shape: Shape::Rect(Locations {
                         from: (539, 243),
                         through: (559, 965)
-                    },
+                    }),
                 },
                 Instruction {
                     typ: Toggle,
-                    locations: Locations {
+                    shape: Shape::Rect(Locations {
                         from: (720, 196),
                         through: (897, 994)
-                    },
+                    }),
                 },
             ]
         );
     }
 
+    fn parse_error(input: &str) -> ParseErrorKind {
+        parse_input_for_grid(input, 10, 5).unwrap_err().kind
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse_input("turn on 0,0 through 1,1\n\n  turn on 0,0 to 1,1"),
+            Err(ParseError {
+                line: 3,
+                text: "turn on 0,0 to 1,1".to_string(),
+                kind: ParseErrorKind::Malformed,
+            })
+        );
+        assert_eq!(
+            parse_error("switch on 0,0 through 1,1"),
+            ParseErrorKind::UnknownInstruction("switch on".to_string())
+        );
+        assert_eq!(
+            parse_error("toggle 0,-1 through 1,1"),
+            ParseErrorKind::InvalidNumber("-1".to_string())
+        );
+        assert_eq!(
+            parse_error("toggle 2,0 through 1,1"),
+            ParseErrorKind::Reversed
+        );
+        assert_eq!(
+            parse_error("toggle 0,0 through 9,5"),
+            ParseErrorKind::OutOfBounds((9, 5))
+        );
+        assert!(parse_input_for_grid("toggle 0,0 through 9,4\n", 10, 5).is_ok());
+    }
+
+    #[test]
+    fn parse_error_message() {
+        let error = parse_input("turn on 0,0 through 1,1\nflip 0,0 through 1,1").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: unknown instruction 'flip'");
+        let error = parse_input("toggle 0,0 to 1,1").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 1: expected '<instruction> <shape>' with a shape of 'X,Y through X,Y', \
+             'circle X,Y r R', 'line X,Y to X,Y' or 'all' but got 'toggle 0,0 to 1,1'"
+        );
+    }
+
+    #[test]
+    fn instruction_display() {
+        let input = "turn on 1,2 through 3,4\nturn off 0,0 through 0,0\ntoggle 5,6 through 7,8";
+        let instructions = parse_input(input).unwrap();
+        let lines: Vec<String> = instructions.iter().map(|x| x.to_string()).collect();
+        assert_eq!(lines.join("\n"), input);
+        assert_eq!(parse_coord("12,3"), Some((12, 3)));
+        assert_eq!(parse_coord("12"), None);
+    }
+
+    #[test]
+    fn locations_on_grid() {
+        let check = |locations: &str| check_locations(&parse_locations(locations).unwrap(), 10, 5);
+        assert_eq!(check("0,0 through 9,4"), Ok(()));
+        assert_eq!(check("3,3 through 3,3"), Ok(()));
+        assert_eq!(
+            check("5,5 through 1,1"),
+            Err("5,5 needs to be less than or equal to 1,1".to_string())
+        );
+        assert_eq!(
+            check("0,0 through 2000,2000"),
+            Err("2000,2000 lies outside of the 10x5 grid".to_string())
+        );
+        assert!(check("0,0 through 9,5").is_err());
+    }
+
+    #[test]
+    fn extended_instructions() {
+        let input = "\
+            \x20toggle circle 500,500 r 30\n\
+            \x20turn on line 0,0 to 10,5\n\
+            \x20set brightness 7 0,0 through 1,1\n\
+            \x20dim by 3 all\n\
+            \x20invert all\
+            ";
+        let instructions = parse_input(input).unwrap();
+        assert_eq!(
+            instructions.iter().map(|x| x.typ).collect::<Vec<_>>(),
+            vec![Toggle, TurnOn, SetBrightness(7), DimBy(3), Toggle]
+        );
+        assert_eq!(
+            instructions[0].shape,
+            Shape::Circle {
+                center: (500, 500),
+                radius: 30
+            }
+        );
+        assert_eq!(instructions[3].shape, Shape::All);
+        assert_eq!(
+            instructions[2].to_string(),
+            "set brightness 7 0,0 through 1,1"
+        );
+        assert_eq!(instructions[1].to_string(), "turn on line 0,0 to 10,5");
+        assert_eq!(
+            parse_input("set brightness x all").unwrap_err().kind,
+            ParseErrorKind::InvalidNumber("x".to_string())
+        );
+    }
+
+    #[test]
+    fn repeat_blocks() {
+        let input = "\
+            \x20turn on 0,0 through 0,0\n\
+            \x20repeat 3 {\n\
+            \x20  toggle 1,1 through 1,1\n\
+            \x20  repeat 2 {\n\
+            \x20    dim by 1 all\n\
+            \x20  }\n\
+            \x20}\n\
+            \x20turn off 0,0 through 0,0\
+            ";
+        let instructions = parse_input(input).unwrap();
+        let lines: Vec<String> = instructions.iter().map(|x| x.to_string()).collect();
+        let block = ["toggle 1,1 through 1,1", "dim by 1 all", "dim by 1 all"];
+        let mut expected = vec!["turn on 0,0 through 0,0"];
+        for _ in 0..3 {
+            expected.extend_from_slice(&block);
+        }
+        expected.push("turn off 0,0 through 0,0");
+        assert_eq!(lines, expected);
+        assert!(parse_input("repeat 0 {\ntoggle all\n}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn repeat_errors() {
+        let error = parse_input("toggle all\nrepeat 2 {\ntoggle all").unwrap_err();
+        assert_eq!(
+            (error.line, error.kind),
+            (2, ParseErrorKind::UnclosedRepeat)
+        );
+        let error = parse_input("toggle all\n}").unwrap_err();
+        assert_eq!(
+            (error.line, error.kind),
+            (2, ParseErrorKind::UnexpectedClose)
+        );
+        let nested = "repeat 100000 {\nrepeat 100000 {\nrepeat 100000 {\ntoggle all\n}\n}\n}";
+        let error = parse_input(nested).unwrap_err();
+        assert_eq!(
+            (error.line, &error.kind),
+            (2, &ParseErrorKind::TooManyInstructions)
+        );
+        assert_eq!(
+            error.to_string(),
+            "line 2: 'repeat 100000 {' expands to more than 1000000 instructions"
+        );
+        let huge = format!("repeat {} {{\ntoggle all\ntoggle all\n}}", usize::MAX);
+        assert_eq!(
+            parse_input(&huge).unwrap_err().kind,
+            ParseErrorKind::TooManyInstructions
+        );
+        assert_eq!(
+            parse_input("repeat 1000000 {\ntoggle all\n}").map(|x| x.len()),
+            Ok(MAX_INSTRUCTIONS)
+        );
+        assert_eq!(
+            parse_error("repeat -1 {"),
+            ParseErrorKind::InvalidNumber("-1".to_string())
+        );
+        assert_eq!(
+            parse_error("toggle circle 10,2 r 3"),
+            ParseErrorKind::OutOfBounds((10, 2))
+        );
+        assert_eq!(
+            parse_error("toggle line 0,0 to 3,5"),
+            ParseErrorKind::OutOfBounds((3, 5))
+        );
+    }
+
+    #[test]
+    fn process_extended_instructions() {
+        let mut grid = Grid::new(10, 10).unwrap();
+        let input = "\
+            \x20turn on all\n\
+            \x20set brightness 5 2,2 through 3,3\n\
+            \x20dim by 2 circle 2,2 r 1\n\
+            \x20set brightness 0 line 0,9 to 9,9\
+            ";
+        for instruction in parse_input(input).unwrap() {
+            process_instruction(&mut grid, &instruction).unwrap();
+        }
+        // 5 cells in the circle, 2 of them at brightness 5
+        assert_eq!(grid.nlit(), 100 - 5 - 10);
+        assert_eq!(grid.brightness(), Ok(100 - 4 - 10 + 4 * 4 - 2 * 2));
+        assert_eq!(grid.dimmables[grid.idx(2, 2)], 3);
+        assert_eq!(grid.dimmables[grid.idx(1, 2)], 0);
+    }
+
+    // Every light ends up above u32::MAX / 2, so the total exceeds u32::MAX
+    const BRIGHT: &str = "\
+        \x20set brightness 2147483647 0,0 through 1,1\n\
+        \x20toggle 0,0 through 1,1\n\
+        \x20turn on 0,0 through 0,0\
+        ";
+
+    #[test]
+    fn brightness_beyond_u32() {
+        let instructions = parse_input(BRIGHT).unwrap();
+        let expected = 4 * (u32::MAX as u64 / 2 + 2) + 1;
+        let mut grid = Grid::new(2, 2).unwrap();
+        let mut compressed = CompressedGrid::new(2, 2, &instructions);
+        let mut segtree = SegTreeGrid::new(2, 2).unwrap();
+        let mut sparse = SparseGrid::new(2, 2);
+        for instruction in &instructions {
+            process_instruction(&mut grid, instruction).unwrap();
+            compressed.process_instruction(instruction).unwrap();
+            segtree.process_instruction(instruction).unwrap();
+            sparse.process_instruction(instruction).unwrap();
+        }
+        assert_eq!(grid.dimmables[0], u32::MAX / 2 + 3);
+        assert_eq!(grid.brightness(), Ok(expected));
+        assert_eq!(compressed.brightness(), Ok(expected));
+        assert_eq!(segtree.brightness(), Ok(expected));
+        assert_eq!(sparse.brightness(), Ok(expected));
+        assert_eq!(run_rule(Dimmer, (2, 2), &instructions), Ok(expected));
+    }
+
+    #[test]
+    fn brightness_overflow() {
+        let mut grid = Grid::new(2, 2).unwrap();
+        let input = "set brightness 4294967294 1,1 through 1,1\ntoggle 0,0 through 1,1";
+        let instructions = parse_input(input).unwrap();
+        process_instruction(&mut grid, &instructions[0]).unwrap();
+        let error = process_instruction(&mut grid, &instructions[1]).unwrap_err();
+        assert_eq!(error, Overflow::Light(4294967294, 2));
+        assert_eq!(
+            error.to_string(),
+            "brightness overflow: 4294967294 + 2 exceeds the maximum of 4294967295"
+        );
+        assert_eq!(
+            total_brightness([(u64::MAX / 2, 2), (1, 2)].iter().cloned()),
+            Err(Overflow::Total)
+        );
+        assert_eq!(
+            total_brightness([(u64::MAX / 2, 2)].iter().cloned()),
+            Ok(u64::MAX - 1)
+        );
+    }
+
+    #[test]
+    fn rectangular_grid() {
+        let mut grid = Grid::new(7, 3).unwrap();
+        let instructions =
+            parse_input_for_grid("turn on 2,0 through 6,1\ntoggle 0,2 through 6,2", 7, 3).unwrap();
+        for instruction in &instructions {
+            process_instruction(&mut grid, instruction).unwrap();
+        }
+        assert_eq!(grid.nlit(), 5 * 2 + 7);
+        assert_eq!(grid.brightness(), Ok(5 * 2 + 7 * 2));
+        assert!(grid.lights.get(6, 1) && !grid.lights.get(1, 1));
+        assert_eq!(grid.dimmables[grid.idx(6, 2)], 2);
+
+        let mut tall = Grid::new(2, 9).unwrap();
+        for instruction in parse_input("turn on 1,0 through 1,8").unwrap() {
+            process_instruction(&mut tall, &instruction).unwrap();
+        }
+        assert_eq!(tall.nlit(), 9);
+        assert!(tall.lights.get(1, 8) && !tall.lights.get(0, 8));
+    }
+
+    #[test]
+    fn single_light_spans() {
+        let mut grid = Grid::new(3, 2).unwrap();
+        grid.apply_span(TurnOn, (1, 2, 2)).unwrap();
+        grid.apply_span(Toggle, (1, 0, 0)).unwrap();
+        grid.apply_span(Toggle, (1, 2, 2)).unwrap();
+        grid.apply_span(TurnOff, (0, 1, 1)).unwrap();
+        assert_eq!(grid.nlit(), 1);
+        assert!(grid.lights.get(0, 1) && !grid.lights.get(2, 1));
+        assert_eq!(grid.dimmables, vec![0, 0, 0, 2, 0, 3]);
+    }
+
+    #[test]
+    fn grid_size() {
+        assert_eq!(parse_size("80x25"), Some((80, 25)));
+        assert_eq!(parse_size("80"), None);
+        assert_eq!(parse_size("0x25"), None);
+        let instructions = parse_input("turn on 2,0 through 6,1\ntoggle 0,2 through 3,9").unwrap();
+        assert_eq!(infer_size(&instructions), (7, 10));
+        assert_eq!(infer_size(&[]), (1, 1));
+        assert_eq!(
+            Grid::new(usize::MAX, 2).err(),
+            Some(TooLarge(usize::MAX, 2))
+        );
+    }
+
     #[test]
     fn process_entire_grid() {
-        let mut grid = Grid::new(1000, 1000);
-        let instructions = parse_input("turn on 0,0 through 999,999");
-        process_instruction(&mut grid, &instructions[0]);
+        let mut grid = Grid::new(1000, 1000).unwrap();
+        let instructions = parse_input("turn on 0,0 through 999,999").unwrap();
+        process_instruction(&mut grid, &instructions[0]).unwrap();
         assert_eq!(grid.nlit(), 1_000_000);
     }
 
     #[test]
     fn process_first_line() {
-        let mut grid = Grid::new(1000, 1000);
-        let instructions = parse_input("toggle 0,0 through 999,0");
-        process_instruction(&mut grid, &instructions[0]);
+        let mut grid = Grid::new(1000, 1000).unwrap();
+        let instructions = parse_input("toggle 0,0 through 999,0").unwrap();
+        process_instruction(&mut grid, &instructions[0]).unwrap();
         assert_eq!(grid.nlit(), 1_000);
     }
 
     #[test]
     fn process_middle_four_on() {
-        let mut grid = Grid::new(1000, 1000);
-        let instructions = parse_input("turn on 499,499 through 500,500");
-        process_instruction(&mut grid, &instructions[0]);
+        let mut grid = Grid::new(1000, 1000).unwrap();
+        let instructions = parse_input("turn on 499,499 through 500,500").unwrap();
+        process_instruction(&mut grid, &instructions[0]).unwrap();
         assert_eq!(grid.nlit(), 4);
     }
 
     #[test]
     fn process_middle_four_off() {
-        let mut grid = Grid::new(1000, 1000);
+        let mut grid = Grid::new(1000, 1000).unwrap();
         let instructions = parse_input(
             "\
             \x20turn on 0,0 through 999,999\n\
             \x20turn off 499,499 through 500,500\n\
         ",
-        );
+        )
+        .unwrap();
         for instruction in instructions {
-            process_instruction(&mut grid, &instruction);
+            process_instruction(&mut grid, &instruction).unwrap();
         }
         assert_eq!(grid.nlit(), 1_000_000 - 4);
     }
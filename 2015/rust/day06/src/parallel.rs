@@ -0,0 +1,127 @@
+/*
+ * Row sharded processing of the dense grid.
+ *
+ * An instruction's effect on one row does not depend on any other row. The grid is therefore
+ * split into bands of consecutive rows and every band gets its own thread, which applies the
+ * spans of all instructions clipped to the band's rows. As bands are disjoint no synchronisation
+ * is needed and the result is identical to processing the instructions one after another.
+ */
+use std::thread;
+
+use super::*;
+
+// Same as calling `process_instruction` for every instruction, using up to `nthreads` threads.
+// On overflow the error of the first overflowing instruction is returned, like sequentially.
+pub fn process_parallel(
+    grid: &mut Grid,
+    instructions: &[Instruction],
+    nthreads: usize,
+) -> Result<(), Overflow> {
+    let (ncols, nrows) = (grid.ncols, grid.nrows);
+    let nbands = nthreads.clamp(1, nrows.max(1));
+    let rows_per_band = nrows.div_ceil(nbands).max(1);
+
+    // split from the bottom so each split only moves a single band
+    let mut bands = vec![];
+    while grid.nrows > rows_per_band {
+        let row0 = (grid.nrows - 1) / rows_per_band * rows_per_band;
+        bands.push((row0, grid.split_off_rows(row0)));
+    }
+    bands.push((0, grid.split_off_rows(0)));
+    bands.reverse();
+
+    // each band stops at its first overflow and reports it with the index of the instruction
+    let (bands, results): (Vec<Grid>, Vec<_>) = thread::scope(|scope| {
+        let handles: Vec<_> = bands
+            .into_iter()
+            .map(|(row0, mut band)| {
+                scope.spawn(move || {
+                    let rows = row0..row0 + band.nrows;
+                    let result =
+                        instructions
+                            .iter()
+                            .enumerate()
+                            .try_for_each(|(idx, instruction)| {
+                                for (row, from, through) in
+                                    instruction.shape.spans_in_rows(ncols, rows.clone())
+                                {
+                                    band.apply_span(instruction.typ, (row - row0, from, through))
+                                        .map_err(|err| (idx, err))?;
+                                }
+                                Ok(())
+                            });
+                    (band, result)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|x| x.join().unwrap()).unzip()
+    });
+
+    let mut bands = bands.into_iter();
+    *grid = bands.next().unwrap();
+    for band in bands {
+        grid.append_rows(band);
+    }
+    // bands are ordered by row, so among equal instructions the topmost overflow wins
+    match results
+        .into_iter()
+        .filter_map(Result::err)
+        .min_by_key(|(idx, _)| *idx)
+    {
+        Some((_, err)) => Err(err),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INSTRUCTIONS: &str = "\
+        \x20turn on 0,0 through 29,16\n\
+        \x20toggle 3,2 through 20,15\n\
+        \x20turn off 0,5 through 29,5\n\
+        \x20turn off 0,5 through 29,6\n\
+        \x20toggle 10,0 through 10,16\n\
+        \x20toggle circle 12,8 r 6\n\
+        \x20set brightness 5 line 0,16 to 29,0\n\
+        \x20dim by 2 circle 20,3 r 4\n\
+        \x20turn on 29,16 through 29,16\
+        ";
+
+    fn sequential(ncols: usize, nrows: usize, instructions: &[Instruction]) -> Grid {
+        let mut grid = Grid::new(ncols, nrows).unwrap();
+        for instruction in instructions {
+            process_instruction(&mut grid, instruction).unwrap();
+        }
+        grid
+    }
+
+    #[test]
+    fn same_as_sequential() {
+        let instructions = parse_input(INSTRUCTIONS).unwrap();
+        let expected = sequential(30, 17, &instructions);
+        for nthreads in [1, 2, 3, 4, 16, 17, 100].iter() {
+            let mut grid = Grid::new(30, 17).unwrap();
+            process_parallel(&mut grid, &instructions, *nthreads).unwrap();
+            assert_eq!(grid.nrows, 17);
+            assert_eq!(grid.nlit(), expected.nlit());
+            assert_eq!(grid.dimmables, expected.dimmables);
+            assert_eq!(
+                render(&grid, Palette::Lights, 1),
+                render(&expected, Palette::Lights, 1)
+            );
+        }
+    }
+
+    #[test]
+    fn continues_from_current_state() {
+        let instructions = parse_input(INSTRUCTIONS).unwrap();
+        let expected = sequential(30, 17, &instructions);
+        let mut grid = Grid::new(30, 17).unwrap();
+        process_parallel(&mut grid, &instructions[..3], 4).unwrap();
+        process_parallel(&mut grid, &instructions[3..], 5).unwrap();
+        assert_eq!(grid.dimmables, expected.dimmables);
+        assert_eq!(grid.nlit(), expected.nlit());
+    }
+}
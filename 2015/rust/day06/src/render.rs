@@ -0,0 +1,384 @@
+/*
+ * Renders the state of a grid into a binary PPM (P6) image, which needs no dependencies and is
+ * understood by most image viewers and converters.
+ *
+ * Large grids can be scaled down in which case each pixel shows the average of the
+ * `scale x scale` cells it covers. Only up to `SAMPLES x SAMPLES` evenly spaced cells of those
+ * are looked at, so rendering takes time in proportion to the image instead of the grid.
+ */
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use super::*;
+
+// Read access to the cells of a grid, x being the column and y the row
+pub trait Raster {
+    fn ncols(&self) -> usize;
+    fn nrows(&self) -> usize;
+    fn light(&self, x: usize, y: usize) -> bool;
+    fn dimmable(&self, x: usize, y: usize) -> u32;
+}
+
+impl Raster for Grid {
+    fn ncols(&self) -> usize {
+        self.ncols
+    }
+
+    fn nrows(&self) -> usize {
+        self.nrows
+    }
+
+    fn light(&self, x: usize, y: usize) -> bool {
+        self.lights.get(x, y)
+    }
+
+    fn dimmable(&self, x: usize, y: usize) -> u32 {
+        self.dimmables[self.idx(x, y)]
+    }
+}
+
+impl Raster for CompressedGrid {
+    fn ncols(&self) -> usize {
+        self.ncols()
+    }
+
+    fn nrows(&self) -> usize {
+        self.nrows()
+    }
+
+    fn light(&self, x: usize, y: usize) -> bool {
+        self.light(x, y)
+    }
+
+    fn dimmable(&self, x: usize, y: usize) -> u32 {
+        self.dimmable(x, y)
+    }
+}
+
+impl Raster for SparseGrid {
+    fn ncols(&self) -> usize {
+        self.ncols()
+    }
+
+    fn nrows(&self) -> usize {
+        self.nrows()
+    }
+
+    fn light(&self, x: usize, y: usize) -> bool {
+        self.light(x, y)
+    }
+
+    fn dimmable(&self, x: usize, y: usize) -> u32 {
+        self.dimmable(x, y)
+    }
+}
+
+impl Raster for Bitmap {
+    fn ncols(&self) -> usize {
+        self.ncols
+    }
+
+    fn nrows(&self) -> usize {
+        self.nrows
+    }
+
+    fn light(&self, x: usize, y: usize) -> bool {
+        self.get(x, y)
+    }
+
+    fn dimmable(&self, x: usize, y: usize) -> u32 {
+        self.get(x, y) as u32
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Palette {
+    // lit lights are white
+    Lights,
+    // brightness from black to white
+    Gray,
+    // brightness from black over red and yellow to white
+    Heat,
+}
+
+impl Palette {
+    pub fn from_name(name: &str) -> Option<Palette> {
+        match name {
+            "lights" => Some(Palette::Lights),
+            "gray" => Some(Palette::Gray),
+            "heat" => Some(Palette::Heat),
+            _ => None,
+        }
+    }
+
+    // Maps a value within 0.0..=1.0 to a color
+    fn color(&self, value: f64) -> [u8; 3] {
+        let channel = |x: f64| (x.clamp(0.0, 1.0) * 255.0).round() as u8;
+        match self {
+            Palette::Lights | Palette::Gray => [channel(value); 3],
+            Palette::Heat => [
+                channel(value * 3.0),
+                channel(value * 3.0 - 1.0),
+                channel(value * 3.0 - 2.0),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl Image {
+    pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for pixel in &self.pixels {
+            out.write_all(pixel)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_ppm(&mut out)?;
+        out.flush()
+    }
+
+    // Reads binary PPMs with 8 bits per channel, as written by `write_ppm`
+    pub fn read_ppm<R: Read>(input: &mut R) -> io::Result<Image> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut bytes = vec![];
+        input.read_to_end(&mut bytes)?;
+
+        // magic, width, height and maxval separated by whitespace and '#' comments
+        let mut fields = vec![];
+        let mut pos = 0;
+        while fields.len() < 4 {
+            match bytes.get(pos) {
+                None => return Err(invalid("truncated header")),
+                Some(b'#') => {
+                    while bytes.get(pos).is_some_and(|x| *x != b'\n') {
+                        pos += 1;
+                    }
+                }
+                Some(x) if x.is_ascii_whitespace() => pos += 1,
+                Some(_) => {
+                    let start = pos;
+                    while bytes.get(pos).is_some_and(|x| !x.is_ascii_whitespace()) {
+                        pos += 1;
+                    }
+                    fields.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+                }
+            }
+        }
+        // exactly one whitespace character separates the header from the pixels
+        pos += 1;
+        if fields[0] != "P6" || fields[3] != "255" {
+            return Err(invalid("only P6 images with a maxval of 255 are supported"));
+        }
+        let dimension = |x: &str| x.parse::<usize>().map_err(|_| invalid("invalid dimension"));
+        let (width, height) = (dimension(&fields[1])?, dimension(&fields[2])?);
+        let npixels = width
+            .checked_mul(height)
+            .filter(|x| x.checked_mul(3).is_some())
+            .ok_or_else(|| invalid("image is too large"))?;
+        let data = bytes.get(pos..).unwrap_or(&[]);
+        if data.len() < npixels * 3 {
+            return Err(invalid("truncated pixel data"));
+        }
+        let pixels = data
+            .chunks(3)
+            .take(npixels)
+            .map(|x| [x[0], x[1], x[2]])
+            .collect();
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn load(path: &str) -> io::Result<Image> {
+        Image::read_ppm(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+// Number of cells per row and per column of a pixel's cells that are averaged
+const SAMPLES: usize = 4;
+
+// Up to SAMPLES evenly spaced values of the range
+fn samples(range: std::ops::Range<usize>) -> impl Iterator<Item = usize> + Clone {
+    let n = range.len().min(SAMPLES);
+    (0..n).map(move |i| range.start + (2 * i + 1) * range.len() / (2 * n))
+}
+
+pub fn render(raster: &impl Raster, palette: Palette, scale: usize) -> Image {
+    render_against(raster, palette, scale, None)
+}
+
+// Like `render`, but brightnesses are normalized against `max` instead of the brightest pixel, so
+// images rendered against the same `max` show the same brightness in the same color
+pub fn render_against(
+    raster: &impl Raster,
+    palette: Palette,
+    scale: usize,
+    max: Option<u32>,
+) -> Image {
+    let scale = scale.max(1);
+    let (ncols, nrows) = (raster.ncols(), raster.nrows());
+    let (width, height) = (ncols.div_ceil(scale), nrows.div_ceil(scale));
+
+    let cell = |x: usize, y: usize| match palette {
+        Palette::Lights => raster.light(x, y) as u32 as f64,
+        Palette::Gray | Palette::Heat => raster.dimmable(x, y) as f64,
+    };
+    let mut values = Vec::with_capacity(width * height);
+    for py in 0..height {
+        for px in 0..width {
+            let (xs, ys) = (
+                samples(px * scale..((px + 1) * scale).min(ncols)),
+                samples(py * scale..((py + 1) * scale).min(nrows)),
+            );
+            let ncells = (xs.clone().count() * ys.clone().count()) as f64;
+            let total: f64 = ys
+                .flat_map(|y| xs.clone().map(move |x| (x, y)))
+                .map(|(x, y)| cell(x, y))
+                .sum();
+            values.push(total / ncells);
+        }
+    }
+
+    let max = match (palette, max) {
+        (Palette::Lights, _) => 1.0,
+        (_, Some(max)) => max as f64,
+        (_, None) => values.iter().cloned().fold(0.0, f64::max),
+    };
+    let pixels = values
+        .into_iter()
+        .map(|x| palette.color(if max > 0.0 { x / max } else { 0.0 }))
+        .collect();
+    Image {
+        width,
+        height,
+        pixels,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BLACK: [u8; 3] = [0, 0, 0];
+    const WHITE: [u8; 3] = [255, 255, 255];
+
+    fn grid(instructions: &str) -> Grid {
+        let mut grid = Grid::new(4, 4).unwrap();
+        for instruction in parse_input(instructions).unwrap() {
+            process_instruction(&mut grid, &instruction).unwrap();
+        }
+        grid
+    }
+
+    #[test]
+    fn render_lights() {
+        let grid = grid("turn on 1,0 through 2,0");
+        let image = render(&grid, Palette::Lights, 1);
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!(&image.pixels[0..4], &[BLACK, WHITE, WHITE, BLACK]);
+        assert!(image.pixels[4..].iter().all(|x| *x == BLACK));
+    }
+
+    #[test]
+    fn render_brightness() {
+        let grid = grid("turn on 0,0 through 0,0\ntoggle 1,0 through 1,0");
+        let gray = render(&grid, Palette::Gray, 1);
+        assert_eq!(&gray.pixels[0..3], &[[128; 3], WHITE, BLACK]);
+        let heat = render(&grid, Palette::Heat, 1);
+        assert_eq!(&heat.pixels[0..3], &[[255, 128, 0], WHITE, BLACK]);
+    }
+
+    #[test]
+    fn render_scaled() {
+        let grid = grid("turn on 0,0 through 1,0\nturn on 2,2 through 3,3");
+        let image = render(&grid, Palette::Lights, 2);
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixels, vec![[128; 3], BLACK, BLACK, WHITE]);
+
+        let image = render(&grid, Palette::Lights, 3);
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixels[3], WHITE);
+    }
+
+    #[test]
+    fn render_sampled() {
+        let mut grid = Grid::new(1000, 1000).unwrap();
+        for instruction in parse_input("turn on 0,0 through 499,999").unwrap() {
+            process_instruction(&mut grid, &instruction).unwrap();
+        }
+        // 4 x 4 cells are sampled, the left half of them is lit
+        let image = render(&grid, Palette::Lights, 1000);
+        assert_eq!(image.pixels, vec![[128; 3]]);
+        assert_eq!(
+            samples(0..1000).collect::<Vec<_>>(),
+            vec![125, 375, 625, 875]
+        );
+        assert_eq!(samples(6..9).collect::<Vec<_>>(), vec![6, 7, 8]);
+    }
+
+    #[test]
+    fn backends_render_the_same() {
+        let input = "turn on 0,0 through 2,1\ntoggle 1,1 through 3,3\nturn off 0,0 through 0,0";
+        let instructions = parse_input(input).unwrap();
+        let mut compressed = CompressedGrid::new(4, 4, &instructions);
+        for instruction in &instructions {
+            compressed.process_instruction(instruction).unwrap();
+        }
+        let grid = grid(input);
+        for palette in [Palette::Lights, Palette::Gray, Palette::Heat].iter() {
+            assert_eq!(render(&grid, *palette, 1), render(&compressed, *palette, 1));
+        }
+    }
+
+    #[test]
+    fn render_rectangular() {
+        let mut grid = Grid::new(5, 2).unwrap();
+        for instruction in parse_input("turn on 4,0 through 4,1\nturn on 0,1 through 0,1").unwrap()
+        {
+            process_instruction(&mut grid, &instruction).unwrap();
+        }
+        let image = render(&grid, Palette::Lights, 1);
+        assert_eq!((image.width, image.height), (5, 2));
+        assert_eq!(
+            image.pixels,
+            vec![BLACK, BLACK, BLACK, BLACK, WHITE, WHITE, BLACK, BLACK, BLACK, WHITE]
+        );
+    }
+
+    #[test]
+    fn ppm_format() {
+        let grid = grid("turn on 0,0 through 0,0");
+        let image = render(&grid, Palette::Lights, 2);
+        let mut out = Vec::new();
+        image.write_ppm(&mut out).unwrap();
+        let mut expected = b"P6\n2 2\n255\n".to_vec();
+        expected.extend_from_slice(&[64, 64, 64]);
+        expected.extend_from_slice(&[0; 9]);
+        assert_eq!(out, expected);
+
+        assert_eq!(Image::read_ppm(&mut out.as_slice()).unwrap(), image);
+        let commented = b"P6 # a comment\n2 1\n255\n\x01\x02\x03\x04\x05\x06";
+        let image = Image::read_ppm(&mut &commented[..]).unwrap();
+        assert_eq!(image.pixels, vec![[1, 2, 3], [4, 5, 6]]);
+        assert!(Image::read_ppm(&mut &b"P6\n2 1\n255\n\x01"[..]).is_err());
+        assert!(Image::read_ppm(&mut &b"P3\n1 1\n255\n0 0 0"[..]).is_err());
+        let huge = b"P6\n18446744073709551615 2\n255\n\x01\x02\x03";
+        let error = Image::read_ppm(&mut &huge[..]).unwrap_err();
+        assert_eq!(error.to_string(), "image is too large");
+    }
+}
@@ -0,0 +1,341 @@
+/*
+ * Pluggable light semantics.
+ *
+ * A `LightRule` defines the state of a single light, how the instructions change it and how the
+ * states of all lights aggregate into one number. The dense `Grid` tracks part 1 and part 2
+ * together by running the `Switch` rule on its bitset and the `Dimmer` rule on its brightness,
+ * while `RuleGrid` runs exactly one rule, so we only pay for what we ask.
+ */
+use super::*;
+
+pub trait LightRule {
+    type State: Clone + Default;
+
+    // turning on and toggling may increase a state beyond what it can hold
+    fn on(&self, state: &mut Self::State) -> Result<(), Overflow>;
+    fn off(&self, state: &mut Self::State);
+    fn toggle(&self, state: &mut Self::State) -> Result<(), Overflow>;
+    fn set(&self, state: &mut Self::State, n: u32);
+    fn dim(&self, state: &mut Self::State, n: u32);
+    // sums may exceed a u64 on huge grids
+    fn aggregate(&self, states: &[Self::State]) -> Result<u64, Overflow>;
+}
+
+// Part 1: lights are either on or off, aggregates to the number of lights that are on
+pub struct Switch;
+
+impl LightRule for Switch {
+    type State = bool;
+
+    fn on(&self, state: &mut bool) -> Result<(), Overflow> {
+        *state = true;
+        Ok(())
+    }
+
+    fn off(&self, state: &mut bool) {
+        *state = false;
+    }
+
+    fn toggle(&self, state: &mut bool) -> Result<(), Overflow> {
+        *state = !*state;
+        Ok(())
+    }
+
+    fn set(&self, state: &mut bool, n: u32) {
+        *state = n > 0;
+    }
+
+    fn dim(&self, state: &mut bool, n: u32) {
+        *state = *state && n == 0;
+    }
+
+    fn aggregate(&self, states: &[bool]) -> Result<u64, Overflow> {
+        Ok(states.iter().filter(|&x| *x).count() as u64)
+    }
+}
+
+impl Switch {
+    // The new states of a light that was (off, on). A transition of a boolean is one of four
+    // functions, which lets grids update many lights at once.
+    pub fn transition(typ: InstructionType) -> (bool, bool) {
+        let switch = |mut light| {
+            apply(&Switch, typ, &mut light).expect("switching never overflows");
+            light
+        };
+        (switch(false), switch(true))
+    }
+}
+
+// Part 2: on adds 1, off removes 1 down to 0 and toggle adds 2, aggregates to total brightness
+pub struct Dimmer;
+
+// What the Dimmer does to a brightness x
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Dim {
+    // max(x + n, 0)
+    Add(i64),
+    Set(u32),
+}
+
+impl Dimmer {
+    // Grids which update many lights at once, like the segment tree, use this instead of `apply`
+    pub fn change(typ: InstructionType) -> Dim {
+        match typ {
+            TurnOn => Dim::Add(1),
+            TurnOff => Dim::Add(-1),
+            Toggle => Dim::Add(2),
+            SetBrightness(n) => Dim::Set(n),
+            DimBy(n) => Dim::Add(-(n as i64)),
+        }
+    }
+
+    fn step(typ: InstructionType, state: &mut u32) -> Result<(), Overflow> {
+        *state = match Dimmer::change(typ) {
+            Dim::Add(n) if n < 0 => state.saturating_sub(n.unsigned_abs() as u32),
+            Dim::Add(n) => brighten(*state, n as u32)?,
+            Dim::Set(n) => n,
+        };
+        Ok(())
+    }
+}
+
+impl LightRule for Dimmer {
+    type State = u32;
+
+    fn on(&self, state: &mut u32) -> Result<(), Overflow> {
+        Dimmer::step(TurnOn, state)
+    }
+
+    fn off(&self, state: &mut u32) {
+        Dimmer::step(TurnOff, state).expect("dimming never overflows");
+    }
+
+    fn toggle(&self, state: &mut u32) -> Result<(), Overflow> {
+        Dimmer::step(Toggle, state)
+    }
+
+    fn set(&self, state: &mut u32, n: u32) {
+        Dimmer::step(SetBrightness(n), state).expect("setting never overflows");
+    }
+
+    fn dim(&self, state: &mut u32, n: u32) {
+        Dimmer::step(DimBy(n), state).expect("dimming never overflows");
+    }
+
+    fn aggregate(&self, states: &[u32]) -> Result<u64, Overflow> {
+        total_brightness(states.iter().map(|x| (1, *x)))
+    }
+}
+
+// Same as the Dimmer, but the brightness of a light never exceeds `max`
+pub struct CappedDimmer {
+    pub max: u32,
+}
+
+impl LightRule for CappedDimmer {
+    type State = u32;
+
+    fn on(&self, state: &mut u32) -> Result<(), Overflow> {
+        *state = state.saturating_add(1).min(self.max);
+        Ok(())
+    }
+
+    fn off(&self, state: &mut u32) {
+        *state = state.saturating_sub(1);
+    }
+
+    fn toggle(&self, state: &mut u32) -> Result<(), Overflow> {
+        *state = state.saturating_add(2).min(self.max);
+        Ok(())
+    }
+
+    fn set(&self, state: &mut u32, n: u32) {
+        *state = n.min(self.max);
+    }
+
+    fn dim(&self, state: &mut u32, n: u32) {
+        *state = state.saturating_sub(n);
+    }
+
+    fn aggregate(&self, states: &[u32]) -> Result<u64, Overflow> {
+        total_brightness(states.iter().map(|x| (1, *x)))
+    }
+}
+
+// Lights with `n` levels, toggle cycles through them, on sets the highest and off the lowest.
+// Aggregates to the sum of all levels.
+pub struct Cycle {
+    pub n: u32,
+}
+
+impl LightRule for Cycle {
+    type State = u32;
+
+    fn on(&self, state: &mut u32) -> Result<(), Overflow> {
+        *state = self.n - 1;
+        Ok(())
+    }
+
+    fn off(&self, state: &mut u32) {
+        *state = 0;
+    }
+
+    fn toggle(&self, state: &mut u32) -> Result<(), Overflow> {
+        *state = (*state + 1) % self.n;
+        Ok(())
+    }
+
+    fn set(&self, state: &mut u32, n: u32) {
+        *state = n.min(self.n - 1);
+    }
+
+    fn dim(&self, state: &mut u32, n: u32) {
+        *state = state.saturating_sub(n);
+    }
+
+    fn aggregate(&self, states: &[u32]) -> Result<u64, Overflow> {
+        total_brightness(states.iter().map(|x| (1, *x)))
+    }
+}
+
+// Applies an instruction of type `typ` to the state of a single light
+pub fn apply<R: LightRule>(
+    rule: &R,
+    typ: InstructionType,
+    state: &mut R::State,
+) -> Result<(), Overflow> {
+    match typ {
+        TurnOn => return rule.on(state),
+        Toggle => return rule.toggle(state),
+        TurnOff => rule.off(state),
+        SetBrightness(n) => rule.set(state, n),
+        DimBy(n) => rule.dim(state, n),
+    }
+    Ok(())
+}
+
+// Applies an instruction of type `typ` to the states of consecutive lights
+pub fn apply_span<R: LightRule>(
+    rule: &R,
+    typ: InstructionType,
+    states: &mut [R::State],
+) -> Result<(), Overflow> {
+    for state in states {
+        apply(rule, typ, state)?;
+    }
+    Ok(())
+}
+
+pub struct RuleGrid<R: LightRule> {
+    rule: R,
+    states: Vec<R::State>,
+    ncols: usize,
+    nrows: usize,
+}
+
+impl<R: LightRule> RuleGrid<R> {
+    pub fn new(rule: R, ncols: usize, nrows: usize) -> Result<Self, TooLarge> {
+        let states = allocate(
+            ncols.checked_mul(nrows),
+            R::State::default(),
+            (ncols, nrows),
+        )?;
+        Ok(Self {
+            rule,
+            states,
+            ncols,
+            nrows,
+        })
+    }
+
+    pub fn process_instruction(&mut self, instruction: &Instruction) -> Result<(), Overflow> {
+        for (y, from, through) in instruction.shape.spans(self.ncols, self.nrows) {
+            let row = y * self.ncols;
+            apply_span(
+                &self.rule,
+                instruction.typ,
+                &mut self.states[row + from..=row + through],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn aggregate(&self) -> Result<u64, Overflow> {
+        self.rule.aggregate(&self.states)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INSTRUCTIONS: &str = "\
+        \x20turn on 0,0 through 9,9\n\
+        \x20toggle 0,0 through 9,0\n\
+        \x20turn off 4,4 through 5,5\n\
+        \x20turn off 4,4 through 5,5\n\
+        \x20toggle 2,3 through 7,8\n\
+        \x20set brightness 4 circle 5,5 r 2\n\
+        \x20dim by 2 line 0,9 to 9,0\
+        ";
+
+    fn run<R: LightRule>(rule: R, instructions: &str) -> Result<u64, Overflow> {
+        let mut grid = RuleGrid::new(rule, 10, 10).unwrap();
+        for instruction in parse_input(instructions).unwrap() {
+            grid.process_instruction(&instruction)?;
+        }
+        grid.aggregate()
+    }
+
+    #[test]
+    fn switch_and_dimmer_match_grid() {
+        let mut grid = Grid::new(10, 10).unwrap();
+        for instruction in parse_input(INSTRUCTIONS).unwrap() {
+            process_instruction(&mut grid, &instruction).unwrap();
+        }
+        assert_eq!(run(Switch, INSTRUCTIONS), Ok(grid.nlit() as u64));
+        assert_eq!(run(Dimmer, INSTRUCTIONS), grid.brightness());
+    }
+
+    #[test]
+    fn dimmer_overflow() {
+        let input = "\
+            \x20set brightness 4294967294 0,0 through 0,0\n\
+            \x20turn on 0,0 through 0,0\n\
+            \x20toggle 0,0 through 0,0\
+            ";
+        assert_eq!(run(Dimmer, input), Err(Overflow::Light(u32::MAX, 2)));
+    }
+
+    #[test]
+    fn capped_dimmer() {
+        let max = CappedDimmer { max: u32::MAX };
+        let input = "set brightness 4294967294 0,0 through 0,0\ntoggle 0,0 through 0,0";
+        assert_eq!(run(max, input), Ok(u32::MAX as u64));
+        assert_eq!(
+            run(CappedDimmer { max: 3 }, "toggle 0,0 through 0,0"),
+            Ok(2)
+        );
+        assert_eq!(
+            run(
+                CappedDimmer { max: 3 },
+                "toggle 0,0 through 0,0\ntoggle 0,0 through 1,0"
+            ),
+            Ok(3 + 2)
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        let toggles = "toggle 0,0 through 0,0\n".repeat(4);
+        assert_eq!(run(Cycle { n: 3 }, &toggles), Ok(1));
+        assert_eq!(run(Cycle { n: 3 }, "turn on 0,0 through 1,1"), Ok(8));
+        assert_eq!(
+            run(
+                Cycle { n: 3 },
+                "turn on 0,0 through 1,1\nturn off 0,0 through 0,1"
+            ),
+            Ok(4)
+        );
+    }
+}
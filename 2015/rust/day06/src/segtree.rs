@@ -0,0 +1,499 @@
+/*
+ * 2D lazy segment tree supporting the instructions as range updates and answering how many
+ * lights are on and how bright they are inside any rectangle after any prefix of instructions.
+ *
+ * Each node covers a rectangle of cells which is split in half along its longer side, so the
+ * tree stays balanced for non-square grids as well. The subtree of a node covering `n` cells
+ * has `2n - 1` nodes, therefore the right child of node `i` is at `i + 2 * ncells(left)`.
+ *
+ * Lights are updated with a set on/set off/flip tag derived from the `Switch` rule.
+ * The `Dimmer` rule either adds to a dimmable light without going below zero, which is an add
+ * followed by raising every cell to at least 0, or sets it. Raising cells to a lower bound cannot be
+ * represented by a simple tag when the cells of a node differ, so we track the minimum, the
+ * second smallest value and how many cells are at the minimum (segment tree beats).
+ * Setting the brightness to n subtracts the node's maximum, which brings every cell to 0 or
+ * below, raises them to 0 and then adds n.
+ */
+use super::*;
+use rules::Dim;
+
+const NONE: i64 = i64::MAX;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum LightTag {
+    Keep,
+    On,
+    Off,
+    Flip,
+}
+
+impl LightTag {
+    // the tag that results from applying `next` after `self`
+    fn then(self, next: LightTag) -> LightTag {
+        match (self, next) {
+            (tag, LightTag::Keep) => tag,
+            (LightTag::Keep, tag) => tag,
+            (_, LightTag::On) => LightTag::On,
+            (_, LightTag::Off) => LightTag::Off,
+            (LightTag::On, LightTag::Flip) => LightTag::Off,
+            (LightTag::Off, LightTag::Flip) => LightTag::On,
+            (LightTag::Flip, LightTag::Flip) => LightTag::Keep,
+        }
+    }
+}
+
+impl From<(bool, bool)> for LightTag {
+    // the new states of a light that was (off, on), see `Switch::transition`
+    fn from(transition: (bool, bool)) -> Self {
+        match transition {
+            (true, true) => LightTag::On,
+            (false, false) => LightTag::Off,
+            (true, false) => LightTag::Flip,
+            (false, true) => LightTag::Keep,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    lit: u64,
+    light_tag: LightTag,
+    brightness: i64,
+    min: i64,
+    // second smallest brightness or NONE if all cells are at `min`
+    min2: i64,
+    nmin: u64,
+    max: i64,
+    add: i64,
+}
+
+// Half open rectangle
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x0: usize,
+    x1: usize,
+    y0: usize,
+    y1: usize,
+}
+
+impl Rect {
+    fn ncells(&self) -> usize {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+
+    fn split(&self) -> (Rect, Rect) {
+        if self.x1 - self.x0 >= self.y1 - self.y0 {
+            let mid = (self.x0 + self.x1) / 2;
+            (Rect { x1: mid, ..*self }, Rect { x0: mid, ..*self })
+        } else {
+            let mid = (self.y0 + self.y1) / 2;
+            (Rect { y1: mid, ..*self }, Rect { y0: mid, ..*self })
+        }
+    }
+
+    fn disjoint(&self, other: &Rect) -> bool {
+        self.x1 <= other.x0 || other.x1 <= self.x0 || self.y1 <= other.y0 || other.y1 <= self.y0
+    }
+
+    fn covers(&self, other: &Rect) -> bool {
+        self.x0 <= other.x0 && other.x1 <= self.x1 && self.y0 <= other.y0 && other.y1 <= self.y1
+    }
+}
+
+impl From<&Locations> for Rect {
+    fn from(locations: &Locations) -> Self {
+        let Locations { from, through } = locations;
+        Rect {
+            x0: from.0,
+            x1: through.0 + 1,
+            y0: from.1,
+            y1: through.1 + 1,
+        }
+    }
+}
+
+pub struct SegTreeGrid {
+    nodes: Vec<Node>,
+    bounds: Rect,
+}
+
+impl SegTreeGrid {
+    // Brightnesses are summed up in an i64, which bounds the number of cells
+    const MAX_CELLS: usize = (i64::MAX / u32::MAX as i64) as usize;
+
+    pub fn new(ncols: usize, nrows: usize) -> Result<Self, TooLarge> {
+        let bounds = Rect {
+            x0: 0,
+            x1: ncols,
+            y0: 0,
+            y1: nrows,
+        };
+        let node = Node {
+            lit: 0,
+            light_tag: LightTag::Keep,
+            brightness: 0,
+            min: 0,
+            min2: NONE,
+            nmin: 0,
+            max: 0,
+            add: 0,
+        };
+        let nnodes = ncols
+            .checked_mul(nrows)
+            .filter(|&ncells| ncells <= SegTreeGrid::MAX_CELLS)
+            .map(|ncells| (2 * ncells).saturating_sub(1));
+        let mut grid = Self {
+            nodes: allocate(nnodes, node, (ncols, nrows))?,
+            bounds,
+        };
+        if !grid.nodes.is_empty() {
+            grid.build(0, bounds);
+        }
+        Ok(grid)
+    }
+
+    fn build(&mut self, idx: usize, rect: Rect) {
+        self.nodes[idx].nmin = rect.ncells() as u64;
+        if rect.ncells() > 1 {
+            let (left, right) = rect.split();
+            self.build(idx + 1, left);
+            self.build(idx + 2 * left.ncells(), right);
+        }
+    }
+
+    fn apply_light(&mut self, idx: usize, ncells: usize, tag: LightTag) {
+        let node = &mut self.nodes[idx];
+        match tag {
+            LightTag::Keep => return,
+            LightTag::On => node.lit = ncells as u64,
+            LightTag::Off => node.lit = 0,
+            LightTag::Flip => node.lit = ncells as u64 - node.lit,
+        }
+        node.light_tag = node.light_tag.then(tag);
+    }
+
+    fn apply_add(&mut self, idx: usize, ncells: usize, add: i64) {
+        let node = &mut self.nodes[idx];
+        node.brightness += add * ncells as i64;
+        node.min += add;
+        node.max += add;
+        if node.min2 != NONE {
+            node.min2 += add;
+        }
+        node.add += add;
+    }
+
+    // Requires min < value < min2, i.e. only the cells at the minimum change
+    fn apply_raise(&mut self, idx: usize, value: i64) {
+        let node = &mut self.nodes[idx];
+        if node.min < value {
+            node.brightness += (value - node.min) * node.nmin as i64;
+            if node.min2 == NONE {
+                node.max = value;
+            }
+            node.min = value;
+        }
+    }
+
+    fn push(&mut self, idx: usize, rect: Rect) {
+        let (left, right) = rect.split();
+        let children = [(idx + 1, left), (idx + 2 * left.ncells(), right)];
+        let Node {
+            light_tag,
+            add,
+            min,
+            ..
+        } = self.nodes[idx];
+        for (child, child_rect) in children.iter() {
+            self.apply_light(*child, child_rect.ncells(), light_tag);
+            if add != 0 {
+                self.apply_add(*child, child_rect.ncells(), add);
+            }
+            self.apply_raise(*child, min);
+        }
+        let node = &mut self.nodes[idx];
+        node.light_tag = LightTag::Keep;
+        node.add = 0;
+    }
+
+    fn pull(&mut self, idx: usize, rect: Rect) {
+        let (left, _) = rect.split();
+        let (l, r) = (self.nodes[idx + 1], self.nodes[idx + 2 * left.ncells()]);
+        let node = &mut self.nodes[idx];
+        node.lit = l.lit + r.lit;
+        node.brightness = l.brightness + r.brightness;
+        node.min = l.min.min(r.min);
+        node.max = l.max.max(r.max);
+        node.nmin = 0;
+        node.min2 = NONE;
+        for child in [l, r].iter() {
+            if child.min == node.min {
+                node.nmin += child.nmin;
+                node.min2 = node.min2.min(child.min2);
+            } else {
+                node.min2 = node.min2.min(child.min);
+            }
+        }
+    }
+
+    fn raise(&mut self, idx: usize, rect: Rect, value: i64) {
+        let node = self.nodes[idx];
+        if node.min >= value {
+            return;
+        }
+        if value < node.min2 {
+            self.apply_raise(idx, value);
+            return;
+        }
+        self.push(idx, rect);
+        let (left, right) = rect.split();
+        self.raise(idx + 1, left, value);
+        self.raise(idx + 2 * left.ncells(), right, value);
+        self.pull(idx, rect);
+    }
+
+    fn update(&mut self, idx: usize, rect: Rect, target: &Rect, typ: InstructionType) {
+        if rect.disjoint(target) {
+            return;
+        }
+        if target.covers(&rect) {
+            let ncells = rect.ncells();
+            self.apply_light(idx, ncells, Switch::transition(typ).into());
+            match Dimmer::change(typ) {
+                Dim::Add(n) => {
+                    self.apply_add(idx, ncells, n);
+                    if n < 0 {
+                        self.raise(idx, rect, 0);
+                    }
+                }
+                Dim::Set(n) => {
+                    let max = self.nodes[idx].max;
+                    self.apply_add(idx, ncells, -max);
+                    self.raise(idx, rect, 0);
+                    self.apply_add(idx, ncells, n as i64);
+                }
+            }
+            return;
+        }
+        self.push(idx, rect);
+        let (left, right) = rect.split();
+        self.update(idx + 1, left, target, typ);
+        self.update(idx + 2 * left.ncells(), right, target, typ);
+        self.pull(idx, rect);
+    }
+
+    // (lit, brightness, max brightness)
+    fn query(&mut self, idx: usize, rect: Rect, target: &Rect) -> (u64, u64, i64) {
+        if rect.disjoint(target) {
+            return (0, 0, 0);
+        }
+        if target.covers(&rect) {
+            let node = &self.nodes[idx];
+            return (node.lit, node.brightness as u64, node.max);
+        }
+        self.push(idx, rect);
+        let (left, right) = rect.split();
+        let (lit_left, brightness_left, max_left) = self.query(idx + 1, left, target);
+        let (lit_right, brightness_right, max_right) =
+            self.query(idx + 2 * left.ncells(), right, target);
+        (
+            lit_left + lit_right,
+            brightness_left + brightness_right,
+            max_left.max(max_right),
+        )
+    }
+
+    pub fn process_instruction(&mut self, instruction: &Instruction) -> Result<(), Overflow> {
+        if self.nodes.is_empty() {
+            return Ok(());
+        }
+        let (ncols, nrows) = (self.bounds.x1, self.bounds.y1);
+        let rects: Vec<Rect> = instruction
+            .shape
+            .rects(ncols, nrows)
+            .iter()
+            .map(Rect::from)
+            .collect();
+        // an overflow is detected before any rectangle is updated, so it leaves the tree unchanged
+        if let Dim::Add(n) = Dimmer::change(instruction.typ) {
+            for rect in &rects {
+                let max = self.query(0, self.bounds, rect).2;
+                if max + n > u32::MAX as i64 {
+                    return Err(Overflow::Light(max as u32, n as u32));
+                }
+            }
+        }
+        for rect in &rects {
+            self.update(0, self.bounds, rect, instruction.typ);
+        }
+        Ok(())
+    }
+
+    pub fn nlit_in(&mut self, locations: &Locations) -> u64 {
+        if self.nodes.is_empty() {
+            return 0;
+        }
+        self.query(0, self.bounds, &locations.into()).0
+    }
+
+    pub fn brightness_in(&mut self, locations: &Locations) -> u64 {
+        if self.nodes.is_empty() {
+            return 0;
+        }
+        self.query(0, self.bounds, &locations.into()).1
+    }
+
+    pub fn nlit(&self) -> u64 {
+        self.nodes.first().map_or(0, |x| x.lit)
+    }
+
+    // Never overflows as MAX_CELLS keeps the total within an i64, but matches the other grids
+    pub fn brightness(&self) -> Result<u64, Overflow> {
+        Ok(self.nodes.first().map_or(0, |x| x.brightness as u64))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Lights on and total brightness within the locations of a dense grid
+    fn dense_in(grid: &Grid, locations: &Locations) -> (u64, u64) {
+        let Locations { from, through } = locations;
+        let mut totals = (0, 0);
+        for x in from.0..=through.0 {
+            for y in from.1..=through.1 {
+                totals.0 += grid.lights.get(x, y) as u64;
+                totals.1 += grid.dimmables[grid.idx(x, y)] as u64;
+            }
+        }
+        totals
+    }
+
+    fn locations(x0: usize, y0: usize, x1: usize, y1: usize) -> Locations {
+        Locations {
+            from: (x0, y0),
+            through: (x1, y1),
+        }
+    }
+
+    #[test]
+    fn single_instructions() {
+        let mut grid = SegTreeGrid::new(1000, 1000).unwrap();
+        for instruction in parse_input("turn on 499,499 through 500,500").unwrap() {
+            grid.process_instruction(&instruction).unwrap();
+        }
+        assert_eq!(grid.nlit(), 4);
+        assert_eq!(grid.nlit_in(&locations(0, 0, 499, 499)), 1);
+        assert_eq!(grid.brightness_in(&locations(500, 0, 999, 999)), 2);
+
+        for instruction in parse_input("turn off 0,0 through 499,999").unwrap() {
+            grid.process_instruction(&instruction).unwrap();
+        }
+        for instruction in parse_input("turn off 0,0 through 499,999").unwrap() {
+            grid.process_instruction(&instruction).unwrap();
+        }
+        assert_eq!(grid.nlit(), 2);
+        assert_eq!(grid.brightness(), Ok(2));
+    }
+
+    const NEAR_MAXIMUM: &str =
+        "set brightness 4294967290 0,0 through 2,2\nturn off 1,1 through 1,1";
+
+    #[test]
+    fn brightness_near_maximum() {
+        let mut grid = SegTreeGrid::new(3, 3).unwrap();
+        for instruction in parse_input(NEAR_MAXIMUM).unwrap() {
+            grid.process_instruction(&instruction).unwrap();
+        }
+        assert_eq!(grid.brightness(), Ok(9 * 4294967290 - 1));
+    }
+
+    #[test]
+    fn brightness_overflow() {
+        let mut grid = SegTreeGrid::new(3, 3).unwrap();
+        for instruction in parse_input(NEAR_MAXIMUM).unwrap() {
+            grid.process_instruction(&instruction).unwrap();
+        }
+        let toggles = parse_input(&"toggle 0,0 through 0,0\n".repeat(3)).unwrap();
+        grid.process_instruction(&toggles[0]).unwrap();
+        grid.process_instruction(&toggles[1]).unwrap();
+        let brightness = grid.brightness();
+        assert_eq!(
+            grid.process_instruction(&toggles[2]),
+            Err(Overflow::Light(4294967294, 2))
+        );
+        assert_eq!(grid.brightness(), brightness);
+        assert_eq!(grid.brightness_in(&locations(0, 0, 0, 0)), 4294967294);
+    }
+
+    #[test]
+    fn too_large() {
+        assert_eq!(
+            SegTreeGrid::new(usize::MAX, 2).err(),
+            Some(TooLarge(usize::MAX, 2))
+        );
+        assert!(SegTreeGrid::new(100_000, 100_000).is_err());
+        assert_eq!(SegTreeGrid::new(7, 3).unwrap().nodes.len(), 41);
+    }
+
+    fn compare_with_dense_grid(ncols: usize, nrows: usize) {
+        // simple LCG to create random instructions which are reproducible
+        let mut seed: u64 = 7;
+        let mut next = |n: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % n
+        };
+
+        let mut grid = Grid::new(ncols, nrows).unwrap();
+        let mut segtree = SegTreeGrid::new(ncols, nrows).unwrap();
+        for _ in 0..200 {
+            let (xa, xb, ya, yb) = (next(ncols), next(ncols), next(nrows), next(nrows));
+            let instruction = Instruction {
+                typ: match next(6) {
+                    0 => TurnOn,
+                    1 => TurnOff,
+                    2 => SetBrightness(next(4) as u32),
+                    3 => DimBy(next(3) as u32),
+                    _ => Toggle,
+                },
+                shape: match next(8) {
+                    0 => Shape::Circle {
+                        center: (xa, ya),
+                        radius: next(8),
+                    },
+                    1 => Shape::Line {
+                        from: (xa, ya),
+                        to: (xb, yb),
+                    },
+                    2 if next(4) == 0 => Shape::All,
+                    _ => Shape::Rect(locations(xa.min(xb), ya.min(yb), xa.max(xb), ya.max(yb))),
+                },
+            };
+            process_instruction(&mut grid, &instruction).unwrap();
+            segtree.process_instruction(&instruction).unwrap();
+
+            assert_eq!(segtree.nlit(), grid.nlit() as u64);
+            assert_eq!(segtree.brightness(), grid.brightness());
+
+            let (xa, xb, ya, yb) = (next(ncols), next(ncols), next(nrows), next(nrows));
+            let query = locations(xa.min(xb), ya.min(yb), xa.max(xb), ya.max(yb));
+            let (lit, brightness) = dense_in(&grid, &query);
+            assert_eq!(segtree.nlit_in(&query), lit);
+            assert_eq!(segtree.brightness_in(&query), brightness);
+        }
+    }
+
+    #[test]
+    fn same_as_dense_grid() {
+        compare_with_dense_grid(40, 40);
+    }
+
+    #[test]
+    fn same_as_dense_rectangular_grid() {
+        compare_with_dense_grid(50, 17);
+        compare_with_dense_grid(3, 45);
+    }
+}
@@ -0,0 +1,307 @@
+/*
+ * The cells an instruction applies to.
+ *
+ * Every shape is broken down into horizontal spans of consecutive cells within a row, which is
+ * what the dense and rule based grids update. The compressed and segment tree backends work on
+ * rectangles, where a span is simply a rectangle that is one row high.
+ * Shapes are clipped to the grid, so a circle around a light near the edge only covers the cells
+ * that exist.
+ */
+use std::ops::Range;
+
+use super::*;
+
+// (row, from_col, through_col)
+pub type Span = (usize, usize, usize);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Shape {
+    Rect(Locations),
+    // all cells whose distance to the center is at most the radius
+    Circle { center: Coord, radius: usize },
+    // Bresenham line from one end to the other, both ends included
+    Line { from: Coord, to: Coord },
+    All,
+}
+
+impl Shape {
+    // The explicitly given coordinates, which need to lie on the grid
+    pub fn coords(&self) -> Vec<Coord> {
+        match self {
+            Shape::Rect(Locations { from, through }) => vec![*from, *through],
+            Shape::Circle { center, .. } => vec![*center],
+            Shape::Line { from, to } => vec![*from, *to],
+            Shape::All => vec![],
+        }
+    }
+
+    // The bottom right corner of the smallest rectangle containing the shape
+    pub fn extent(&self) -> Option<Coord> {
+        match self {
+            Shape::Rect(locations) => Some(locations.through),
+            Shape::Circle { center, radius } => Some((
+                center.0.saturating_add(*radius),
+                center.1.saturating_add(*radius),
+            )),
+            Shape::Line { from, to } => Some((from.0.max(to.0), from.1.max(to.1))),
+            Shape::All => None,
+        }
+    }
+
+    // Disjoint spans covering the shape, sorted by row and column
+    pub fn spans(&self, ncols: usize, nrows: usize) -> Vec<Span> {
+        self.spans_in_rows(ncols, 0..nrows)
+    }
+
+    // The spans within `rows` only, without generating the spans of the other rows
+    pub fn spans_in_rows(&self, ncols: usize, rows: Range<usize>) -> Vec<Span> {
+        if ncols == 0 || rows.is_empty() {
+            return vec![];
+        }
+        let (last_col, first_row, last_row) = (ncols - 1, rows.start, rows.end - 1);
+        match self {
+            Shape::Rect(Locations { from, through }) => {
+                if from.0 > last_col {
+                    return vec![];
+                }
+                (from.1.max(first_row)..=through.1.min(last_row))
+                    .map(|row| (row, from.0, through.0.min(last_col)))
+                    .collect()
+            }
+            Shape::Circle { center, radius } => {
+                let (cx, cy) = *center;
+                let rows = cy.saturating_sub(*radius).max(first_row)
+                    ..=cy.saturating_add(*radius).min(last_row);
+                rows.filter_map(|row| {
+                    let dy = cy.max(row) - cy.min(row);
+                    // squares of any usize fit into a u128, the half width is at most radius
+                    let (radius, dy) = (*radius as u128, dy as u128);
+                    let half = (radius * radius - dy * dy).isqrt() as usize;
+                    let (from, through) = (
+                        cx.saturating_sub(half),
+                        cx.saturating_add(half).min(last_col),
+                    );
+                    if from <= through {
+                        Some((row, from, through))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+            }
+            Shape::Line { from, to } => {
+                let mut cells: Vec<Coord> = line_cells(*from, *to)
+                    .into_iter()
+                    .filter(|&(x, y)| x <= last_col && rows.contains(&y))
+                    .collect();
+                cells.sort_unstable_by_key(|&(x, y)| (y, x));
+                let mut spans: Vec<Span> = vec![];
+                for (x, y) in cells {
+                    match spans.last_mut() {
+                        Some((row, _, through)) if *row == y && *through + 1 == x => *through = x,
+                        _ => spans.push((y, x, x)),
+                    }
+                }
+                spans
+            }
+            Shape::All => rows.map(|row| (row, 0, last_col)).collect(),
+        }
+    }
+
+    // Disjoint rectangles covering the shape
+    pub fn rects(&self, ncols: usize, nrows: usize) -> Vec<Locations> {
+        match self {
+            Shape::Rect(Locations { from, through }) if from.0 < ncols && from.1 < nrows => {
+                vec![Locations {
+                    from: *from,
+                    through: (through.0.min(ncols - 1), through.1.min(nrows - 1)),
+                }]
+            }
+            Shape::All if ncols > 0 && nrows > 0 => vec![Locations {
+                from: (0, 0),
+                through: (ncols - 1, nrows - 1),
+            }],
+            _ => self
+                .spans(ncols, nrows)
+                .into_iter()
+                .map(|(row, from, through)| Locations {
+                    from: (from, row),
+                    through: (through, row),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn contains(&self, x: usize, y: usize, ncols: usize, nrows: usize) -> bool {
+        self.spans(ncols, nrows)
+            .iter()
+            .any(|&(row, from, through)| row == y && from <= x && x <= through)
+    }
+}
+
+fn line_cells(from: Coord, to: Coord) -> Vec<Coord> {
+    let (mut x, mut y) = (from.0 as i64, from.1 as i64);
+    let (x1, y1) = (to.0 as i64, to.1 as i64);
+    let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+    let (sx, sy) = ((x1 - x).signum(), (y1 - y).signum());
+    let mut err = dx + dy;
+    let mut cells = vec![];
+    loop {
+        cells.push((x as usize, y as usize));
+        if x == x1 && y == y1 {
+            return cells;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shape::Rect(Locations { from, through }) => write!(
+                f,
+                "{},{} through {},{}",
+                from.0, from.1, through.0, through.1
+            ),
+            Shape::Circle { center, radius } => {
+                write!(f, "circle {},{} r {}", center.0, center.1, radius)
+            }
+            Shape::Line { from, to } => {
+                write!(f, "line {},{} to {},{}", from.0, from.1, to.0, to.1)
+            }
+            Shape::All => write!(f, "all"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ncells(spans: &[Span]) -> usize {
+        spans
+            .iter()
+            .map(|(_, from, through)| through - from + 1)
+            .sum()
+    }
+
+    #[test]
+    fn rect_spans() {
+        let rect = Shape::Rect(Locations {
+            from: (2, 1),
+            through: (4, 3),
+        });
+        assert_eq!(rect.spans(10, 10), vec![(1, 2, 4), (2, 2, 4), (3, 2, 4)]);
+        assert_eq!(rect.spans(4, 3), vec![(1, 2, 3), (2, 2, 3)]);
+        assert_eq!(rect.rects(4, 3).len(), 1);
+    }
+
+    #[test]
+    fn circle_cells() {
+        let circle = |x, y, radius| Shape::Circle {
+            center: (x, y),
+            radius,
+        };
+        assert_eq!(circle(5, 5, 0).spans(10, 10), vec![(5, 5, 5)]);
+        assert_eq!(
+            circle(5, 5, 1).spans(10, 10),
+            vec![(4, 5, 5), (5, 4, 6), (6, 5, 5)]
+        );
+        assert_eq!(
+            circle(5, 5, 2).spans(10, 10),
+            vec![(3, 5, 5), (4, 4, 6), (5, 3, 7), (6, 4, 6), (7, 5, 5)]
+        );
+        // the number of lattice points within a circle of radius 10 is 317
+        assert_eq!(ncells(&circle(50, 50, 10).spans(100, 100)), 317);
+        // clipped at the edges
+        assert_eq!(
+            circle(0, 0, 2).spans(10, 10),
+            vec![(0, 0, 2), (1, 0, 1), (2, 0, 0)]
+        );
+        // huge circles and circles off the grid
+        assert_eq!(
+            circle(5, 5, usize::MAX).spans(3, 2),
+            vec![(0, 0, 2), (1, 0, 2)]
+        );
+        assert_eq!(
+            circle(usize::MAX, 1, usize::MAX).extent(),
+            Some((usize::MAX, usize::MAX))
+        );
+        assert_eq!(circle(usize::MAX, 0, 3).spans(10, 10), vec![]);
+        assert_eq!(circle(11, 0, 2).spans(10, 10), vec![(0, 9, 9)]);
+        assert!(circle(5, 5, 2).contains(7, 5, 10, 10));
+        assert!(!circle(5, 5, 2).contains(7, 6, 10, 10));
+    }
+
+    #[test]
+    fn line_spans() {
+        let line = |from, to| Shape::Line { from, to };
+        assert_eq!(line((1, 2), (4, 2)).spans(10, 10), vec![(2, 1, 4)]);
+        assert_eq!(
+            line((3, 0), (3, 2)).spans(10, 10),
+            vec![(0, 3, 3), (1, 3, 3), (2, 3, 3)]
+        );
+        assert_eq!(
+            line((0, 0), (3, 3)).spans(10, 10),
+            vec![(0, 0, 0), (1, 1, 1), (2, 2, 2), (3, 3, 3)]
+        );
+        assert_eq!(
+            line((6, 1), (0, 0)).spans(10, 10),
+            vec![(0, 0, 3), (1, 4, 6)]
+        );
+        // the same cells in both directions
+        assert_eq!(
+            ncells(&line((0, 0), (9, 4)).spans(10, 10)),
+            ncells(&line((9, 4), (0, 0)).spans(10, 10))
+        );
+    }
+
+    #[test]
+    fn spans_in_rows() {
+        let shapes = [
+            Shape::Rect(Locations {
+                from: (2, 1),
+                through: (4, 8),
+            }),
+            Shape::Circle {
+                center: (5, 5),
+                radius: 3,
+            },
+            Shape::Line {
+                from: (0, 9),
+                to: (9, 0),
+            },
+            Shape::All,
+        ];
+        for shape in shapes.iter() {
+            for rows in [0..10, 0..3, 3..7, 7..10, 9..10, 5..5].iter() {
+                let expected: Vec<Span> = shape
+                    .spans(10, 10)
+                    .into_iter()
+                    .filter(|(row, _, _)| rows.contains(row))
+                    .collect();
+                assert_eq!(shape.spans_in_rows(10, rows.clone()), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn all_cells() {
+        assert_eq!(Shape::All.spans(3, 2), vec![(0, 0, 2), (1, 0, 2)]);
+        assert_eq!(
+            Shape::All.rects(3, 2),
+            vec![Locations {
+                from: (0, 0),
+                through: (2, 1)
+            }]
+        );
+    }
+}
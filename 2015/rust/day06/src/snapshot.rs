@@ -0,0 +1,361 @@
+/*
+ * Binary snapshots of the dense grid, so long runs can be checkpointed and resumed and states
+ * produced by different tools can be compared.
+ *
+ * All numbers are unsigned LEB128 varints. The format is
+ *
+ *   "D06S" version ncols nrows position
+ *   nruns (run length)*      lights in row major order, alternating off and on starting with off
+ *   nruns (value length)*    brightness in row major order
+ *
+ * where `position` is the number of instructions that were applied to the grid.
+ */
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+};
+
+use super::*;
+
+const MAGIC: &[u8; 4] = b"D06S";
+const VERSION: u64 = 1;
+
+fn write_varint<W: Write>(out: &mut W, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return out.write_all(&[byte]);
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_varint<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint is too long"))
+}
+
+// Run length encodes the values as (value, length) pairs
+fn runs<T: PartialEq>(values: impl Iterator<Item = T>) -> Vec<(T, u64)> {
+    let mut runs: Vec<(T, u64)> = vec![];
+    for value in values {
+        match runs.last_mut() {
+            Some((last, length)) if *last == value => *length += 1,
+            _ => runs.push((value, 1)),
+        }
+    }
+    runs
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GridDiff {
+    pub count: usize,
+    // the smallest rectangle containing all differing cells
+    pub bounds: Option<Locations>,
+}
+
+impl fmt::Display for GridDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.bounds {
+            Some(Locations { from, through }) => write!(
+                f,
+                "{} cells differ within {},{} through {},{}",
+                self.count, from.0, from.1, through.0, through.1
+            ),
+            None => write!(f, "no cells differ"),
+        }
+    }
+}
+
+impl Grid {
+    fn cells(&self) -> impl Iterator<Item = Coord> + '_ {
+        (0..self.nrows).flat_map(move |y| (0..self.ncols).map(move |x| (x, y)))
+    }
+
+    pub fn write_snapshot<W: Write>(&self, out: &mut W, position: usize) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        for value in [
+            VERSION,
+            self.ncols as u64,
+            self.nrows as u64,
+            position as u64,
+        ]
+        .iter()
+        {
+            write_varint(out, *value)?;
+        }
+
+        let mut lights = runs(self.cells().map(|(x, y)| self.lights.get(x, y)));
+        if lights.first().is_some_and(|(lit, _)| *lit) {
+            lights.insert(0, (false, 0));
+        }
+        write_varint(out, lights.len() as u64)?;
+        for (_, length) in lights {
+            write_varint(out, length)?;
+        }
+
+        let dimmables = runs(self.dimmables.iter());
+        write_varint(out, dimmables.len() as u64)?;
+        for (value, length) in dimmables {
+            write_varint(out, *value as u64)?;
+            write_varint(out, length)?;
+        }
+        Ok(())
+    }
+
+    // Returns the grid and the number of instructions that were applied to it
+    pub fn read_snapshot<R: Read>(input: &mut R) -> io::Result<(Grid, usize)> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a grid snapshot"));
+        }
+        if read_varint(input)? != VERSION {
+            return Err(invalid("unsupported snapshot version"));
+        }
+        let ncols = read_varint(input)? as usize;
+        let nrows = read_varint(input)? as usize;
+        let position = read_varint(input)? as usize;
+        let ncells = ncols
+            .checked_mul(nrows)
+            .ok_or_else(|| invalid("grid is too large"))?;
+
+        // the runs need to cover the grid before anything the size of the grid is allocated
+        let mut lights = vec![];
+        let mut cell = 0;
+        for _ in 0..read_varint(input)? {
+            let length = read_varint(input)? as usize;
+            if length > ncells - cell {
+                return Err(invalid("lights exceed the grid"));
+            }
+            lights.push(length);
+            cell += length;
+        }
+        if cell != ncells {
+            return Err(invalid("lights do not cover the grid"));
+        }
+        let mut dimmables = vec![];
+        cell = 0;
+        for _ in 0..read_varint(input)? {
+            let value = read_varint(input)?;
+            let length = read_varint(input)? as usize;
+            if length > ncells - cell || value > u32::MAX as u64 {
+                return Err(invalid("invalid brightness"));
+            }
+            dimmables.push((value as u32, length));
+            cell += length;
+        }
+        if cell != ncells {
+            return Err(invalid("brightness does not cover the grid"));
+        }
+
+        let mut grid = Grid::new(ncols, nrows).map_err(|err| invalid(&err.to_string()))?;
+        let mut cell = 0;
+        for (idx, length) in lights.into_iter().enumerate() {
+            if idx % 2 == 1 {
+                // split the run into the spans of the rows it crosses
+                let end = cell + length;
+                let mut start = cell;
+                while start < end {
+                    let (row, col) = (start / ncols, start % ncols);
+                    let through = (ncols - 1).min(col + end - start - 1);
+                    grid.lights.on_span(row, col, through);
+                    start += through - col + 1;
+                }
+            }
+            cell += length;
+        }
+        let mut cell = 0;
+        for (value, length) in dimmables {
+            for dimmable in &mut grid.dimmables[cell..cell + length] {
+                *dimmable = value;
+            }
+            cell += length;
+        }
+        Ok((grid, position))
+    }
+
+    pub fn save(&self, path: &str, position: usize) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_snapshot(&mut out, position)?;
+        out.flush()
+    }
+
+    pub fn load(path: &str) -> io::Result<(Grid, usize)> {
+        Grid::read_snapshot(&mut BufReader::new(File::open(path)?))
+    }
+
+    // Compares the lights and brightness of two grids of the same size
+    pub fn diff(&self, other: &Grid) -> GridDiff {
+        assert_eq!(
+            (self.ncols, self.nrows),
+            (other.ncols, other.nrows),
+            "grids of different sizes"
+        );
+        let mut diff = GridDiff {
+            count: 0,
+            bounds: None,
+        };
+        for (x, y) in self.cells() {
+            let idx = self.idx(x, y);
+            if self.lights.get(x, y) == other.lights.get(x, y)
+                && self.dimmables[idx] == other.dimmables[idx]
+            {
+                continue;
+            }
+            diff.count += 1;
+            diff.bounds = Some(match diff.bounds {
+                None => Locations {
+                    from: (x, y),
+                    through: (x, y),
+                },
+                Some(Locations { from, through }) => Locations {
+                    from: (from.0.min(x), from.1.min(y)),
+                    through: (through.0.max(x), through.1.max(y)),
+                },
+            });
+        }
+        diff
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INSTRUCTIONS: &str = "\
+        \x20turn on 0,0 through 69,9\n\
+        \x20toggle 3,2 through 66,8\n\
+        \x20turn off 60,0 through 69,9\n\
+        \x20set brightness 9 circle 20,5 r 4\n\
+        \x20toggle 69,9 through 69,9\
+        ";
+
+    fn grid(instructions: &str) -> Grid {
+        let mut grid = Grid::new(70, 10).unwrap();
+        for instruction in parse_input(instructions).unwrap() {
+            process_instruction(&mut grid, &instruction).unwrap();
+        }
+        grid
+    }
+
+    fn round_trip(grid: &Grid, position: usize) -> (Grid, usize, usize) {
+        let mut bytes = vec![];
+        grid.write_snapshot(&mut bytes, position).unwrap();
+        let (loaded, loaded_position) = Grid::read_snapshot(&mut bytes.as_slice()).unwrap();
+        (loaded, loaded_position, bytes.len())
+    }
+
+    #[test]
+    fn varints() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX].iter() {
+            let mut bytes = vec![];
+            write_varint(&mut bytes, *value).unwrap();
+            assert_eq!(read_varint(&mut bytes.as_slice()).unwrap(), *value);
+        }
+        let mut bytes = vec![];
+        write_varint(&mut bytes, 300).unwrap();
+        assert_eq!(bytes, vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let grid = grid(INSTRUCTIONS);
+        let (loaded, position, _) = round_trip(&grid, 5);
+        assert_eq!(position, 5);
+        assert_eq!((loaded.ncols, loaded.nrows), (70, 10));
+        assert_eq!(loaded.nlit(), grid.nlit());
+        assert_eq!(loaded.dimmables, grid.dimmables);
+        assert_eq!(grid.diff(&loaded).count, 0);
+
+        // starting with a lit light
+        let grid = self::grid("turn on 0,0 through 1,0\ntoggle 10,3 through 69,9");
+        let (loaded, _, _) = round_trip(&grid, 2);
+        assert_eq!(grid.diff(&loaded).count, 0);
+    }
+
+    #[test]
+    fn compact() {
+        let (_, _, nbytes) = round_trip(&Grid::new(1000, 1000).unwrap(), 0);
+        assert!(nbytes < 32);
+        let (_, _, nbytes) = round_trip(&grid("turn on 0,0 through 499,999"), 1);
+        assert!(nbytes < 10_000);
+    }
+
+    #[test]
+    fn invalid_snapshots() {
+        let mut bytes = vec![];
+        grid(INSTRUCTIONS).write_snapshot(&mut bytes, 5).unwrap();
+        for end in [3, 10, bytes.len() - 1].iter() {
+            assert!(Grid::read_snapshot(&mut &bytes[..*end]).is_err());
+        }
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        let error = Grid::read_snapshot(&mut wrong_magic.as_slice())
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oversized_snapshots() {
+        let snapshot = |ncols: u64, nrows: u64, runs: &[u64]| {
+            let mut bytes = MAGIC.to_vec();
+            for value in [VERSION, ncols, nrows, 0].iter().chain(runs) {
+                write_varint(&mut bytes, *value).unwrap();
+            }
+            Grid::read_snapshot(&mut bytes.as_slice())
+                .map(|_| ())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(snapshot(u64::MAX, 2, &[]), "grid is too large");
+        // a huge grid whose runs only cover a single cell
+        assert_eq!(
+            snapshot(1 << 20, 1 << 20, &[1, 1, 1, 0, 1]),
+            "lights do not cover the grid"
+        );
+        assert_eq!(
+            snapshot(1 << 20, 1 << 20, &[1, 1 << 40, 1, 0, 1]),
+            "brightness does not cover the grid"
+        );
+        assert_eq!(snapshot(2, 2, &[1, 5]), "lights exceed the grid");
+    }
+
+    #[test]
+    fn diff() {
+        let before = grid(INSTRUCTIONS);
+        let after = grid(&format!(
+            "{}\ntoggle 10,2 through 12,3\ndim by 1 5,7 through 5,7",
+            INSTRUCTIONS
+        ));
+        assert_eq!(
+            before.diff(&after),
+            GridDiff {
+                count: 7,
+                bounds: Some(Locations {
+                    from: (5, 2),
+                    through: (12, 7)
+                })
+            }
+        );
+        assert_eq!(before.diff(&before).to_string(), "no cells differ");
+        assert_eq!(
+            before.diff(&after).to_string(),
+            "7 cells differ within 5,2 through 12,7"
+        );
+    }
+}
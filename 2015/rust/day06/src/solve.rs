@@ -0,0 +1,183 @@
+/*
+ * Reverse engineering of instructions from a picture.
+ *
+ * The lit cells are greedily covered with rectangles: the first uncovered lit cell in row major
+ * order starts a rectangle which is extended to the right and then down as long as all of its
+ * cells are lit. Turning on the rectangles draws the picture, overlaps are harmless as turning on
+ * is idempotent. For mostly lit pictures it is shorter to turn on everything and cover the dark
+ * cells with rectangles that are turned off instead, so both are tried and the shorter one wins.
+ */
+use super::*;
+
+pub struct Bitmap {
+    pub ncols: usize,
+    pub nrows: usize,
+    pub cells: Vec<bool>,
+}
+
+impl Bitmap {
+    // Pixels at least half as bright as white are lit
+    pub fn from_image(image: &Image) -> Self {
+        Self {
+            ncols: image.width,
+            nrows: image.height,
+            cells: image
+                .pixels
+                .iter()
+                .map(|x| x.iter().map(|c| *c as u32).sum::<u32>() >= 3 * 128)
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.cells[y * self.ncols + x]
+    }
+
+    // Covers the cells equal to `target` with rectangles of type `typ`
+    fn cover(&self, target: bool, typ: InstructionType) -> Vec<Instruction> {
+        let mut covered = vec![false; self.cells.len()];
+        let mut instructions = vec![];
+        for y in 0..self.nrows {
+            for x in 0..self.ncols {
+                if self.get(x, y) != target || covered[y * self.ncols + x] {
+                    continue;
+                }
+                let mut through = (x, y);
+                while through.0 + 1 < self.ncols && self.get(through.0 + 1, y) == target {
+                    through.0 += 1;
+                }
+                while through.1 + 1 < self.nrows
+                    && (x..=through.0).all(|col| self.get(col, through.1 + 1) == target)
+                {
+                    through.1 += 1;
+                }
+                for row in y..=through.1 {
+                    for flag in &mut covered[row * self.ncols + x..=row * self.ncols + through.0] {
+                        *flag = true;
+                    }
+                }
+                instructions.push(Instruction {
+                    shape: Shape::Rect(Locations {
+                        from: (x, y),
+                        through,
+                    }),
+                    typ,
+                });
+            }
+        }
+        instructions
+    }
+}
+
+// Instructions which draw the lit cells of `bitmap` on a dark grid of the same size
+pub fn solve(bitmap: &Bitmap) -> Vec<Instruction> {
+    let lit = bitmap.cover(true, TurnOn);
+    if bitmap.cells.is_empty() {
+        return lit;
+    }
+    let mut inverse = vec![Instruction {
+        shape: Shape::Rect(Locations {
+            from: (0, 0),
+            through: (bitmap.ncols - 1, bitmap.nrows - 1),
+        }),
+        typ: TurnOn,
+    }];
+    inverse.extend(bitmap.cover(false, TurnOff));
+    if inverse.len() < lit.len() {
+        inverse
+    } else {
+        lit
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bitmap(text: &str) -> Bitmap {
+        let rows: Vec<&str> = text.lines().map(|x| x.trim()).collect();
+        Bitmap {
+            ncols: rows[0].len(),
+            nrows: rows.len(),
+            cells: rows
+                .iter()
+                .flat_map(|x| x.chars().map(|c| c == '#'))
+                .collect(),
+        }
+    }
+
+    // Runs the instructions and checks the rendered grid against the rendered bitmap
+    fn draws(bitmap: &Bitmap, instructions: &[Instruction]) -> bool {
+        let mut grid = Grid::new(bitmap.ncols, bitmap.nrows).unwrap();
+        for instruction in instructions {
+            process_instruction(&mut grid, instruction).unwrap();
+        }
+        render(&grid, Palette::Lights, 1) == render(bitmap, Palette::Lights, 1)
+    }
+
+    #[test]
+    fn single_rectangle() {
+        let target = bitmap(
+            "......
+             .###..
+             .###..
+             ......",
+        );
+        let instructions = solve(&target);
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>(),
+            vec!["turn on 1,1 through 3,2"]
+        );
+        assert!(draws(&target, &instructions));
+    }
+
+    #[test]
+    fn mostly_lit() {
+        let target = bitmap(
+            "######
+             #.####
+             ######
+             ####.#",
+        );
+        let instructions = solve(&target);
+        assert_eq!(instructions.len(), 3);
+        assert!(draws(&target, &instructions));
+    }
+
+    #[test]
+    fn picture() {
+        let target = bitmap(
+            "..#####...
+             .#.....#..
+             #..#.#..#.
+             #.......#.
+             #.#...#.#.
+             #..###..#.
+             .#.....#..
+             ..#####...",
+        );
+        let instructions = solve(&target);
+        assert!(draws(&target, &instructions));
+        let nlit = target.cells.iter().filter(|x| **x).count();
+        assert!(instructions.len() < nlit);
+        assert!(solve(&bitmap("....\n....")).is_empty());
+    }
+
+    #[test]
+    fn from_rendered_image() {
+        let mut grid = Grid::new(30, 20).unwrap();
+        let input =
+            "toggle circle 15,10 r 8\nturn off 0,9 through 29,10\ntoggle 12,0 through 17,19";
+        for instruction in parse_input(input).unwrap() {
+            process_instruction(&mut grid, &instruction).unwrap();
+        }
+        let image = render(&grid, Palette::Lights, 1);
+        let target = Bitmap::from_image(&image);
+        let instructions = solve(&target);
+        assert!(draws(&target, &instructions));
+        assert_eq!(target.cells.iter().filter(|x| **x).count(), grid.nlit());
+    }
+}
@@ -0,0 +1,212 @@
+/*
+ * Sparse alternative to the dense `Grid` for huge boards that are mostly dark.
+ *
+ * Each row is a sorted set of runs, i.e. maximal intervals of columns whose lights are in the same
+ * state. Consecutive rows which are identical share a single entry, so the grid is a sorted set of
+ * bands of rows, each holding the runs of its rows. Untouched areas are a single dark run and
+ * neighbouring runs or bands that end up in the same state are merged again, which keeps the
+ * memory proportional to the outlines of the lit regions rather than to `ncols * nrows`.
+ * Unlike the `CompressedGrid`, the instructions do not need to be known upfront.
+ */
+use std::collections::BTreeMap;
+
+use super::*;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+struct Cell {
+    lit: bool,
+    brightness: u32,
+}
+
+// Maps the first column/row of every run/band to its value, which lasts until the next key
+type Runs<T> = BTreeMap<usize, T>;
+
+// Makes sure a run starts at `key`, unless `key` is past the last run
+fn split_at<T: Clone>(runs: &mut Runs<T>, key: usize, end: usize) {
+    if key >= end || runs.contains_key(&key) {
+        return;
+    }
+    let value = runs.range(..key).next_back().unwrap().1.clone();
+    runs.insert(key, value);
+}
+
+// Merges the runs starting within from..=through with their predecessor if they are equal
+fn merge<T: PartialEq>(runs: &mut Runs<T>, from: usize, through: usize) {
+    let keys: Vec<usize> = runs.range(from..=through).map(|(key, _)| *key).collect();
+    for key in keys {
+        let previous = runs.range(..key).next_back().map(|(_, value)| value);
+        if previous.is_some() && previous == runs.get(&key) {
+            runs.remove(&key);
+        }
+    }
+}
+
+fn run_lengths<T>(runs: &Runs<T>, end: usize) -> impl Iterator<Item = (usize, &T)> {
+    let ends = runs.keys().skip(1).cloned().chain(std::iter::once(end));
+    runs.iter()
+        .zip(ends)
+        .map(|((start, value), end)| (end - start, value))
+}
+
+fn lookup<T>(runs: &Runs<T>, key: usize) -> &T {
+    runs.range(..=key).next_back().unwrap().1
+}
+
+pub struct SparseGrid {
+    ncols: usize,
+    nrows: usize,
+    bands: Runs<Runs<Cell>>,
+}
+
+impl SparseGrid {
+    pub fn new(ncols: usize, nrows: usize) -> Self {
+        let mut row = Runs::new();
+        row.insert(0, Cell::default());
+        let mut bands = Runs::new();
+        bands.insert(0, row);
+        Self {
+            ncols,
+            nrows,
+            bands,
+        }
+    }
+
+    pub fn process_instruction(&mut self, instruction: &Instruction) -> Result<(), Overflow> {
+        for rect in instruction.shape.rects(self.ncols, self.nrows) {
+            self.process_rect(instruction.typ, &rect)?;
+        }
+        Ok(())
+    }
+
+    fn process_rect(
+        &mut self,
+        typ: InstructionType,
+        locations: &Locations,
+    ) -> Result<(), Overflow> {
+        let Locations { from, through } = locations;
+        split_at(&mut self.bands, from.1, self.nrows);
+        split_at(&mut self.bands, through.1 + 1, self.nrows);
+        for (_, row) in self.bands.range_mut(from.1..=through.1) {
+            split_at(row, from.0, self.ncols);
+            split_at(row, through.0 + 1, self.ncols);
+            for (_, cell) in row.range_mut(from.0..=through.0) {
+                rules::apply(&Switch, typ, &mut cell.lit)?;
+                rules::apply(&Dimmer, typ, &mut cell.brightness)?;
+            }
+            merge(row, from.0, through.0 + 1);
+        }
+        merge(&mut self.bands, from.1, through.1 + 1);
+        Ok(())
+    }
+
+    pub fn ncols(&self) -> usize {
+        self.ncols
+    }
+
+    pub fn nrows(&self) -> usize {
+        self.nrows
+    }
+
+    pub fn light(&self, x: usize, y: usize) -> bool {
+        lookup(lookup(&self.bands, y), x).lit
+    }
+
+    pub fn dimmable(&self, x: usize, y: usize) -> u32 {
+        lookup(lookup(&self.bands, y), x).brightness
+    }
+
+    // The area of every run together with its cell
+    fn areas(&self) -> impl Iterator<Item = (u64, &Cell)> + '_ {
+        run_lengths(&self.bands, self.nrows).flat_map(move |(height, row)| {
+            run_lengths(row, self.ncols)
+                .map(move |(width, cell)| (height as u64 * width as u64, cell))
+        })
+    }
+
+    pub fn nlit(&self) -> u64 {
+        self.areas()
+            .filter(|(_, cell)| cell.lit)
+            .map(|(area, _)| area)
+            .sum()
+    }
+
+    pub fn brightness(&self) -> Result<u64, Overflow> {
+        total_brightness(self.areas().map(|(area, cell)| (area, cell.brightness)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INSTRUCTIONS: &str = "\
+        \x20turn on 0,0 through 59,39\n\
+        \x20toggle 0,0 through 59,0\n\
+        \x20turn off 29,19 through 30,20\n\
+        \x20toggle circle 30,20 r 12\n\
+        \x20set brightness 3 line 0,39 to 59,10\n\
+        \x20dim by 2 10,5 through 50,35\n\
+        \x20turn on 5,5 through 5,5\n\
+        \x20invert all\
+        ";
+
+    // The number of runs over all bands, which is what the memory usage is proportional to
+    fn nruns(sparse: &SparseGrid) -> usize {
+        sparse.bands.values().map(|row| row.len()).sum()
+    }
+
+    #[test]
+    fn same_as_dense_grid() {
+        let mut grid = Grid::new(60, 40).unwrap();
+        let mut sparse = SparseGrid::new(60, 40);
+        for instruction in parse_input(INSTRUCTIONS).unwrap() {
+            process_instruction(&mut grid, &instruction).unwrap();
+            sparse.process_instruction(&instruction).unwrap();
+            assert_eq!(sparse.nlit(), grid.nlit() as u64);
+            assert_eq!(sparse.brightness(), grid.brightness());
+        }
+        for palette in [Palette::Lights, Palette::Gray].iter() {
+            assert_eq!(render(&grid, *palette, 1), render(&sparse, *palette, 1));
+        }
+    }
+
+    #[test]
+    fn runs_are_merged() {
+        let mut sparse = SparseGrid::new(100, 100);
+        assert_eq!(nruns(&sparse), 1);
+        for instruction in parse_input("turn on 10,10 through 19,19").unwrap() {
+            sparse.process_instruction(&instruction).unwrap();
+        }
+        // a band above, one with three runs and one below
+        assert_eq!(nruns(&sparse), 5);
+        let input = "turn off 10,10 through 19,19\nturn off 10,10 through 19,19";
+        for instruction in parse_input(input).unwrap() {
+            sparse.process_instruction(&instruction).unwrap();
+        }
+        assert_eq!(nruns(&sparse), 1);
+        assert_eq!((sparse.nlit(), sparse.brightness()), (0, Ok(0)));
+    }
+
+    #[test]
+    fn huge_board() {
+        let n = 1_000_000_000;
+        let mut sparse = SparseGrid::new(n, n);
+        let input = "\
+            \x20turn on 0,0 through 999999999,999999999\n\
+            \x20toggle 0,0 through 999999999,0\n\
+            \x20turn off 1,1 through 2,2\n\
+            \x20toggle circle 500000000,500000000 r 20\
+            ";
+        for instruction in parse_input(input).unwrap() {
+            sparse.process_instruction(&instruction).unwrap();
+        }
+        // a circle of radius 20 covers 1257 cells
+        assert_eq!(
+            sparse.nlit(),
+            1_000_000_000_000_000_000 - 1_000_000_000 - 4 - 1257
+        );
+        assert!(nruns(&sparse) < 200);
+        assert!(!sparse.light(500_000_000, 500_000_020));
+        assert!(sparse.light(500_000_000, 500_000_021));
+    }
+}
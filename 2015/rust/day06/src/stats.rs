@@ -0,0 +1,212 @@
+/*
+ * Per instruction statistics to understand what an input does.
+ *
+ * While the instructions are processed on a dense grid, every span an instruction covers is
+ * compared before and after the update, which tells how many lights were switched and how many
+ * changed their brightness. The same changes keep the running totals up to date without scanning
+ * the grid. Besides that we count how often each cell was touched, which renders into a heat map
+ * of the hot spots.
+ */
+use super::*;
+
+#[derive(Debug, PartialEq)]
+pub struct InstructionStats {
+    // cells whose light was switched on or off
+    pub switched: usize,
+    // cells whose brightness changed
+    pub dimmed: usize,
+    // totals after the instruction
+    pub nlit: usize,
+    pub brightness: u64,
+}
+
+// How many instructions touched each cell
+pub struct Touches {
+    ncols: usize,
+    nrows: usize,
+    counts: Vec<u32>,
+}
+
+impl Touches {
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.counts[y * self.ncols + x]
+    }
+
+    // The most touched cell and how often it was touched
+    pub fn hottest(&self) -> Option<(Coord, u32)> {
+        let idx = (0..self.counts.len()).max_by_key(|idx| (self.counts[*idx], usize::MAX - idx))?;
+        Some(((idx % self.ncols, idx / self.ncols), self.counts[idx]))
+    }
+}
+
+impl Raster for Touches {
+    fn ncols(&self) -> usize {
+        self.ncols
+    }
+
+    fn nrows(&self) -> usize {
+        self.nrows
+    }
+
+    fn light(&self, x: usize, y: usize) -> bool {
+        self.get(x, y) > 0
+    }
+
+    fn dimmable(&self, x: usize, y: usize) -> u32 {
+        self.get(x, y)
+    }
+}
+
+pub struct Report {
+    pub stats: Vec<InstructionStats>,
+    pub touches: Touches,
+}
+
+impl Report {
+    pub fn new(grid: &mut Grid, instructions: &[Instruction]) -> Result<Self, Overflow> {
+        let mut touches = Touches {
+            ncols: grid.ncols,
+            nrows: grid.nrows,
+            counts: vec![0; grid.ncols * grid.nrows],
+        };
+        let mut stats = vec![];
+        let (mut nlit, mut brightness) = (grid.nlit(), grid.brightness()?);
+        // the lights of a span before it is updated
+        let mut before = vec![];
+        for instruction in instructions {
+            let (mut switched, mut dimmed) = (0, 0);
+            for span in instruction.shape.spans(grid.ncols, grid.nrows) {
+                let (row, from, through) = span;
+                before.clear();
+                before.extend(
+                    (from..=through)
+                        .map(|x| (grid.lights.get(x, row), grid.dimmables[grid.idx(x, row)])),
+                );
+                grid.apply_span(instruction.typ, span)?;
+                for (x, &(light, dimmable)) in (from..=through).zip(&before) {
+                    let idx = grid.idx(x, row);
+                    let (new_light, new_dimmable) = (grid.lights.get(x, row), grid.dimmables[idx]);
+                    if new_light != light {
+                        switched += 1;
+                        nlit = if new_light { nlit + 1 } else { nlit - 1 };
+                    }
+                    if new_dimmable != dimmable {
+                        dimmed += 1;
+                        brightness = (brightness - dimmable as u64)
+                            .checked_add(new_dimmable as u64)
+                            .ok_or(Overflow::Total)?;
+                    }
+                    touches.counts[idx] += 1;
+                }
+            }
+            stats.push(InstructionStats {
+                switched,
+                dimmed,
+                nlit,
+                brightness,
+            });
+        }
+        Ok(Self { stats, touches })
+    }
+
+    pub fn format(&self, instructions: &[Instruction]) -> String {
+        let mut out = format!(
+            "{:>5}  {:>9}  {:>9}  {:>9}  {:>10}  instruction\n",
+            "#", "switched", "dimmed", "lit", "brightness"
+        );
+        for (idx, (stats, instruction)) in self.stats.iter().zip(instructions).enumerate() {
+            out += &format!(
+                "{:>5}  {:>9}  {:>9}  {:>9}  {:>10}  {}\n",
+                idx + 1,
+                stats.switched,
+                stats.dimmed,
+                stats.nlit,
+                stats.brightness,
+                instruction
+            );
+        }
+        if let Some(((x, y), count)) = self.touches.hottest() {
+            out += &format!("hottest light: {},{} touched {} times\n", x, y, count);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const INSTRUCTIONS: &str = "\
+        \x20turn on 0,0 through 3,3\n\
+        \x20turn on 0,0 through 1,1\n\
+        \x20turn off 2,2 through 5,5\n\
+        \x20turn off 2,2 through 5,5\n\
+        \x20toggle 0,0 through 0,1\
+        ";
+
+    #[test]
+    fn per_instruction_stats() {
+        let instructions = parse_input(INSTRUCTIONS).unwrap();
+        let mut grid = Grid::new(6, 6).unwrap();
+        let report = Report::new(&mut grid, &instructions).unwrap();
+        let changes: Vec<(usize, usize)> = report
+            .stats
+            .iter()
+            .map(|x| (x.switched, x.dimmed))
+            .collect();
+        assert_eq!(changes, vec![(16, 16), (0, 4), (4, 4), (0, 0), (2, 2)]);
+        let totals: Vec<(usize, u64)> = report
+            .stats
+            .iter()
+            .map(|x| (x.nlit, x.brightness))
+            .collect();
+        assert_eq!(
+            totals,
+            vec![(16, 16), (16, 20), (12, 16), (12, 16), (10, 20)]
+        );
+        assert_eq!((grid.nlit(), grid.brightness()), (10, Ok(20)));
+    }
+
+    #[test]
+    fn totals_of_a_lit_grid() {
+        let mut grid = Grid::new(6, 6).unwrap();
+        for instruction in parse_input("toggle 0,0 through 5,5").unwrap() {
+            process_instruction(&mut grid, &instruction).unwrap();
+        }
+        let instructions = parse_input(INSTRUCTIONS).unwrap();
+        let report = Report::new(&mut grid, &instructions).unwrap();
+        let last = report.stats.last().unwrap();
+        assert_eq!(
+            (last.nlit, Ok(last.brightness)),
+            (grid.nlit(), grid.brightness())
+        );
+    }
+
+    #[test]
+    fn touches() {
+        let instructions = parse_input(INSTRUCTIONS).unwrap();
+        let report = Report::new(&mut Grid::new(6, 6).unwrap(), &instructions).unwrap();
+        assert_eq!(report.touches.get(0, 0), 3);
+        assert_eq!(report.touches.get(3, 3), 3);
+        assert_eq!(report.touches.get(5, 0), 0);
+        assert_eq!(report.touches.hottest(), Some(((0, 0), 3)));
+
+        let heat = render(&report.touches, Palette::Heat, 1);
+        assert_eq!(heat.pixels[0], [255, 255, 255]);
+        assert_eq!(heat.pixels[5], [0, 0, 0]);
+    }
+
+    #[test]
+    fn formatted_report() {
+        let instructions = parse_input(INSTRUCTIONS).unwrap();
+        let report = Report::new(&mut Grid::new(6, 6).unwrap(), &instructions).unwrap();
+        let text = report.format(&instructions);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(
+            lines[3],
+            "    3          4          4         12          16  turn off 2,2 through 5,5"
+        );
+        assert_eq!(lines[6], "hottest light: 0,0 touched 3 times");
+    }
+}
@@ -0,0 +1,324 @@
+/*
+ * Command line interface to evaluate arbitrary netlists without rebuilding.
+ *
+ *   day07 [--wire <wire>]... [--set <wire>=<value>]... [--json] [--faults] [--diff <file>]
+ *         [<file>|-]...
+ *
+ * All files are concatenated into one program, so modules may be defined in a separate file.
+ * With `-` or when only options are given the program is read from stdin.
+ */
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+};
+
+use super::*;
+
+pub const USAGE: &str = "\
+usage: day07 [--wire <wire>]... [--set <wire>=<value>]... [--json] [--faults] [--diff <file>]
+             [<file>|-]...
+
+  --wire <wire>          print the value of <wire>, all wires are printed if none is given
+  --set <wire>=<value>   force <wire> to <value> before the circuit runs
+  --json                 print the wire values as a JSON object
+  --faults               print the fault coverage of the chosen wires instead of their values
+  --diff <file>          print the gates and wire values that change from the netlist to <file>
+  <file>                 netlist to load, use - to read from stdin (default when no file is given)
+
+Without any arguments both parts of the puzzle are solved for the bundled input.
+";
+
+#[derive(Debug, PartialEq, Default)]
+pub struct Options {
+    pub files: Vec<String>,
+    pub wires: Vec<String>,
+    pub slots: Slots,
+    pub json: bool,
+    pub faults: bool,
+    pub diff: Option<String>,
+}
+
+fn parse_set(set: &str) -> Result<(String, SlotValue), String> {
+    let mut parts = set.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(wire), Some(value)) if !wire.is_empty() => {
+            let value = value
+                .parse::<SlotValue>()
+                .map_err(|_| format!("Invalid value '{}' for wire '{}'", value, wire))?;
+            Ok((wire.to_string(), value))
+        }
+        _ => Err(format!("Expected <wire>=<value> but got '{}'", set)),
+    }
+}
+
+impl Options {
+    pub fn from_args<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for '{}'", flag))
+            };
+            match arg.as_str() {
+                "--wire" => options.wires.push(value("--wire")?),
+                "--set" => {
+                    let (wire, value) = parse_set(&value("--set")?)?;
+                    options.slots.insert(wire, value);
+                }
+                "--json" => options.json = true,
+                "--faults" => options.faults = true,
+                "--diff" => options.diff = Some(value("--diff")?),
+                "-" => options.files.push(arg),
+                flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+                _ => options.files.push(arg),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn read_lines<R: BufRead>(reader: R, program: &mut String) -> io::Result<()> {
+    for line in reader.lines() {
+        program.push_str(&line?);
+        program.push('\n');
+    }
+    Ok(())
+}
+
+pub fn load_program(files: &[String]) -> io::Result<String> {
+    let mut program = String::new();
+    if files.is_empty() {
+        read_lines(io::stdin().lock(), &mut program)?;
+    }
+    for file in files {
+        if file == "-" {
+            read_lines(io::stdin().lock(), &mut program)?;
+        } else {
+            read_lines(BufReader::new(File::open(file)?), &mut program)?;
+        }
+    }
+    Ok(program)
+}
+
+pub fn format_plain(wires: &[String], slots: &Slots) -> String {
+    wires
+        .iter()
+        .map(|wire| match slots.get(wire) {
+            Some(value) => format!("{}: {}\n", wire, value),
+            None => format!("{}: unknown\n", wire),
+        })
+        .collect()
+}
+
+// Wire names given on the command line may contain any character
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+pub fn format_json(wires: &[String], slots: &Slots) -> String {
+    let entries: Vec<String> = wires
+        .iter()
+        .map(|wire| match slots.get(wire) {
+            Some(value) => format!("{}: {}", json_string(wire), value),
+            None => format!("{}: null", json_string(wire)),
+        })
+        .collect();
+    format!("{{{}}}\n", entries.join(", "))
+}
+
+fn check_inputs(circuit: &Circuit, slots: &Slots) -> Result<(), String> {
+    match circuit.missing_inputs(slots).as_slice() {
+        [] => Ok(()),
+        missing => Err(format!(
+            "Input wires without a value: {}, use --set <wire>=<value>",
+            missing.join(", ")
+        )),
+    }
+}
+
+pub fn run(options: Options) -> Result<String, String> {
+    let program = load_program(&options.files).map_err(|err| err.to_string())?;
+    let circuit = Circuit::parse(true, &program)?;
+
+    if options.faults {
+        let outputs: Vec<&str> = match options.wires.len() {
+            0 => vec!["a"],
+            _ => options.wires.iter().map(|x| x.as_str()).collect(),
+        };
+        if let Some(output) = outputs.iter().find(|x| !circuit.has_wire(x)) {
+            return Err(format!(
+                "Unknown output wire '{}', use --wire <wire>",
+                output
+            ));
+        }
+        check_inputs(&circuit, &options.slots)?;
+        return Ok(circuit.fault_coverage(&options.slots, &outputs).to_string());
+    }
+
+    if let Some(file) = options.diff {
+        let program = load_program(&[file]).map_err(|err| err.to_string())?;
+        let other = Circuit::parse(true, &program)?;
+        let diff = circuit.diff(&other, &options.slots, &options.wires);
+        return match diff.is_empty() {
+            true => Ok("no differences\n".to_string()),
+            false => Ok(diff.to_string()),
+        };
+    }
+
+    check_inputs(&circuit, &options.slots)?;
+    let slots = circuit.run_with_slots(options.slots);
+    let wires = match options.wires.len() {
+        0 => {
+            let mut wires: Vec<String> = slots.keys().cloned().collect();
+            wires.sort();
+            wires
+        }
+        _ => options.wires,
+    };
+    match options.json {
+        true => Ok(format_json(&wires, &slots)),
+        false => Ok(format_plain(&wires, &slots)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn parse_options() {
+        let options = Options::from_args(args(&[
+            "--wire", "a", "--set", "b=123", "--wire", "c", "--json", "one.txt", "-",
+        ]))
+        .unwrap();
+        let mut slots = HashMap::new();
+        slots.insert("b".to_string(), 123);
+        assert_eq!(
+            options,
+            Options {
+                files: vec!["one.txt".to_string(), "-".to_string()],
+                wires: vec!["a".to_string(), "c".to_string()],
+                slots,
+                json: true,
+                faults: false,
+                diff: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_invalid_options() {
+        assert_eq!(
+            Options::from_args(args(&["--set", "b=70000"])),
+            Err("Invalid value '70000' for wire 'b'".to_string())
+        );
+        assert_eq!(
+            Options::from_args(args(&["--set", "b"])),
+            Err("Expected <wire>=<value> but got 'b'".to_string())
+        );
+        assert_eq!(
+            Options::from_args(args(&["--wire"])),
+            Err("Missing value for '--wire'".to_string())
+        );
+        assert_eq!(
+            Options::from_args(args(&["--verbose"])),
+            Err("Unknown option '--verbose'".to_string())
+        );
+    }
+
+    #[test]
+    fn format_wires() {
+        let mut slots = HashMap::new();
+        slots.insert("a".to_string(), 1);
+        slots.insert("b".to_string(), 2);
+        let wires = vec!["b".to_string(), "a".to_string(), "z".to_string()];
+        assert_eq!(format_plain(&wires, &slots), "b: 2\na: 1\nz: unknown\n");
+        assert_eq!(
+            format_json(&wires, &slots),
+            "{\"b\": 2, \"a\": 1, \"z\": null}\n"
+        );
+        let wires = vec!["a\"b\\c\n".to_string()];
+        assert_eq!(
+            format_json(&wires, &slots),
+            "{\"a\\\"b\\\\c\\u000a\": null}\n"
+        );
+    }
+
+    #[test]
+    fn run_files() {
+        let dir = std::env::temp_dir().join(format!("day07-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let modules = dir.join("modules.txt");
+        let main = dir.join("main.txt");
+        std::fs::write(&modules, "module inv(a) -> (b) {\nNOT a -> b\n}\n").unwrap();
+        std::fs::write(&main, "x -> y\ninv(y) -> (z)\n").unwrap();
+
+        let options = Options::from_args(args(&[
+            "--wire",
+            "z",
+            "--set",
+            "x=65535",
+            modules.to_str().unwrap(),
+            main.to_str().unwrap(),
+        ]))
+        .unwrap();
+        let result = run(options);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result, Ok("z: 0\n".to_string()));
+    }
+
+    #[test]
+    fn invalid_programs() {
+        let dir = std::env::temp_dir().join(format!("day07-cli-errors-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let run_program = |program: &str, extra: &[&str]| {
+            let file = dir.join("program.txt");
+            std::fs::write(&file, program).unwrap();
+            let mut argv = extra.to_vec();
+            argv.push(file.to_str().unwrap());
+            run(Options::from_args(args(&argv)).unwrap())
+        };
+        let malformed = run_program("123 -> x\nx XOR 1 -> y\n", &[]);
+        let unset = run_program("x AND y -> z\nz -> w\n", &[]);
+        let set = run_program(
+            "x AND y -> z\n",
+            &["--set", "x=3", "--set", "y=6", "--wire", "z"],
+        );
+        let faults = run_program("x -> z\n", &["--faults", "--wire", "z"]);
+        let no_output = run_program("123 -> x\n", &["--faults"]);
+        let cycle = run_program("x -> y\ny -> x\n", &[]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(malformed, Err("Unable to add 'x XOR 1 -> y'".to_string()));
+        assert_eq!(
+            unset,
+            Err("Input wires without a value: x, y, use --set <wire>=<value>".to_string())
+        );
+        assert_eq!(set, Ok("z: 2\n".to_string()));
+        assert!(faults
+            .unwrap_err()
+            .starts_with("Input wires without a value: x"));
+        assert_eq!(
+            no_output,
+            Err("Unknown output wire 'a', use --wire <wire>".to_string())
+        );
+        assert!(cycle.unwrap_err().starts_with("Circuit contains a cycle"));
+    }
+}
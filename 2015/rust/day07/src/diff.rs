@@ -0,0 +1,281 @@
+/*
+ * Semantic differences between two versions of a circuit.
+ *
+ * Gates are keyed by their assignee since each wire is only assigned once. Two gates are
+ * considered the same if they compute the same expression, i.e. the operand order of `AND` and
+ * `OR` does not matter and neither does the line the gate was defined on.
+ * Both circuits are then evaluated with the same slots to find the wires whose value changed.
+ * Wires that can't be computed because a gate they depend on was removed are reported as unknown.
+ */
+use std::fmt;
+
+use super::*;
+
+#[derive(Debug, PartialEq)]
+pub struct WireChange {
+    pub wire: String,
+    pub before: Option<SlotValue>,
+    pub after: Option<SlotValue>,
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct CircuitDiff {
+    pub added: Vec<Stmt>,
+    pub removed: Vec<Stmt>,
+    // (before, after)
+    pub changed: Vec<(Stmt, Stmt)>,
+    pub wires: Vec<WireChange>,
+}
+
+impl CircuitDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+            && self.wires.is_empty()
+    }
+}
+
+fn same_gate(a: &Stmt, b: &Stmt) -> bool {
+    match (a, b) {
+        (AssignExpr(_, a1, op_a, a2, _), AssignExpr(_, b1, op_b, b2, _)) => {
+            let commutative = *op_a == And || *op_a == Or;
+            op_a == op_b && ((a1 == b1 && a2 == b2) || (commutative && a1 == b2 && a2 == b1))
+        }
+        _ => a == b,
+    }
+}
+
+fn gates_by_assignee(circuit: &Circuit) -> HashMap<&String, &Stmt> {
+    circuit
+        .statements
+        .iter()
+        .map(|stmt| (stmt.assignee(), stmt))
+        .collect()
+}
+
+fn display_value(value: Option<SlotValue>) -> String {
+    match value {
+        Some(x) => x.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+impl fmt::Display for CircuitDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for stmt in &self.added {
+            writeln!(f, "+ {}", stmt)?;
+        }
+        for stmt in &self.removed {
+            writeln!(f, "- {}", stmt)?;
+        }
+        for (before, after) in &self.changed {
+            writeln!(f, "~ {}  =>  {}", before, after)?;
+        }
+        for change in &self.wires {
+            writeln!(
+                f,
+                "{}: {} -> {}",
+                change.wire,
+                display_value(change.before),
+                display_value(change.after)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Circuit {
+    /*
+     * Reports the gates that differ between `self` and `other` as well as the wires whose value
+     * changes when both circuits are run with the given `slots`.
+     * Only `wires` are compared unless it is empty in which case all wires are.
+     */
+    pub fn diff(&self, other: &Circuit, slots: &Slots, wires: &[String]) -> CircuitDiff {
+        let before = gates_by_assignee(self);
+        let after = gates_by_assignee(other);
+
+        let mut diff = CircuitDiff::default();
+        for (assignee, stmt) in &after {
+            match before.get(assignee) {
+                None => diff.added.push((*stmt).clone()),
+                Some(old) if !same_gate(old, stmt) => {
+                    diff.changed.push(((*old).clone(), (*stmt).clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for (assignee, stmt) in &before {
+            if !after.contains_key(assignee) {
+                diff.removed.push((*stmt).clone());
+            }
+        }
+        diff.added.sort_by(|a, b| a.assignee().cmp(b.assignee()));
+        diff.removed.sort_by(|a, b| a.assignee().cmp(b.assignee()));
+        diff.changed
+            .sort_by(|(a, _), (b, _)| a.assignee().cmp(b.assignee()));
+
+        let slots_before = self.run_partial(slots.clone());
+        let slots_after = other.run_partial(slots.clone());
+        let mut wires: Vec<String> = match wires.len() {
+            0 => slots_before
+                .keys()
+                .chain(slots_after.keys())
+                .cloned()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect(),
+            _ => wires.to_vec(),
+        };
+        wires.sort();
+        diff.wires = wires
+            .into_iter()
+            .filter_map(|wire| {
+                let before = slots_before.get(&wire).copied();
+                let after = slots_after.get(&wire).copied();
+                match before == after {
+                    true => None,
+                    false => Some(WireChange {
+                        wire,
+                        before,
+                        after,
+                    }),
+                }
+            })
+            .collect();
+        diff
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BEFORE: &str = "\
+        \x20123 -> x\n\
+        \x20456 -> y\n\
+        \x20x AND y -> d\n\
+        \x20x OR y -> e\n\
+        \x20NOT x -> h\
+        ";
+
+    #[test]
+    fn reordered_program_has_no_diff() {
+        let after = "\
+            \x20NOT x -> h\n\
+            \x20y OR x -> e\n\
+            \x20456 -> y\n\
+            \x20y AND x -> d\n\
+            \x20123 -> x\
+            ";
+        let before = Circuit::from_program(true, BEFORE);
+        let after = Circuit::from_program(true, after);
+        assert!(before.diff(&after, &HashMap::new(), &[]).is_empty());
+    }
+
+    #[test]
+    fn gate_changes() {
+        let after = "\
+            \x20123 -> x\n\
+            \x20456 -> y\n\
+            \x20x OR y -> d\n\
+            \x20x OR y -> e\n\
+            \x20y RSHIFT 2 -> g\
+            ";
+        let before = Circuit::from_program(true, BEFORE);
+        let after = Circuit::from_program(true, after);
+        let diff = before.diff(&after, &HashMap::new(), &[]);
+
+        let stmts = |program: &str| Circuit::from_program(true, program).statements;
+        assert_eq!(diff.added, stmts("y RSHIFT 2 -> g"));
+        assert_eq!(diff.removed, stmts("NOT x -> h"));
+        assert_eq!(
+            diff.changed,
+            vec![(
+                stmts("x AND y -> d").remove(0),
+                stmts("x OR y -> d").remove(0)
+            )]
+        );
+        assert_eq!(
+            diff.wires,
+            vec![
+                WireChange {
+                    wire: "d".to_string(),
+                    before: Some(72),
+                    after: Some(507),
+                },
+                WireChange {
+                    wire: "g".to_string(),
+                    before: None,
+                    after: Some(114),
+                },
+                WireChange {
+                    wire: "h".to_string(),
+                    before: Some(65412),
+                    after: None,
+                },
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "\
+            + y RSHIFT 2 -> g\n\
+            - NOT x -> h\n\
+            ~ x AND y -> d  =>  x OR y -> d\n\
+            d: 72 -> 507\n\
+            g: unknown -> 114\n\
+            h: 65412 -> unknown\n\
+            "
+        );
+    }
+
+    #[test]
+    fn chosen_wires_and_slots() {
+        let after = BEFORE.replace("123 -> x", "124 -> x");
+        let before = Circuit::from_program(true, BEFORE);
+        let after = Circuit::from_program(true, &after);
+
+        let diff = before.diff(&after, &HashMap::new(), &["d".to_string()]);
+        assert_eq!(diff.changed.len(), 1);
+        // 123 AND 456 == 124 AND 456
+        assert!(diff.wires.is_empty());
+
+        let diff = before.diff(&after, &HashMap::new(), &["e".to_string()]);
+        assert_eq!(diff.wires.len(), 1);
+
+        // forcing x hides the change of its gate from all downstream wires
+        let mut slots = HashMap::new();
+        slots.insert("x".to_string(), 1);
+        let diff = before.diff(&after, &slots, &[]);
+        assert_eq!(diff.changed.len(), 1);
+        assert!(diff.wires.is_empty());
+    }
+
+    #[test]
+    fn removed_driving_gate() {
+        let after = BEFORE.replace(" 123 -> x\n", "");
+        let before = Circuit::from_program(true, BEFORE);
+        let after = Circuit::from_program(true, &after);
+        let diff = before.diff(&after, &HashMap::new(), &[]);
+
+        assert_eq!(
+            diff.removed,
+            Circuit::from_program(true, "123 -> x").statements
+        );
+        assert_eq!(
+            diff.to_string(),
+            "\
+            - 123 -> x\n\
+            d: 72 -> unknown\n\
+            e: 507 -> unknown\n\
+            h: 65412 -> unknown\n\
+            x: 123 -> unknown\n\
+            "
+        );
+
+        // a forced value stands in for the removed gate
+        let mut slots = HashMap::new();
+        slots.insert("x".to_string(), 123);
+        assert_eq!(before.diff(&after, &slots, &[]).wires, vec![]);
+    }
+}
@@ -0,0 +1,217 @@
+/*
+ * Fault injection on top of `run_with_slots`.
+ *
+ * A fault is injected by pre-populating the slot of the faulty wire, the same way part 2 forces
+ * `b`. Since every wire is only assigned once the statement driving that wire is then skipped and
+ * all downstream statements see the faulty value.
+ * A fault is observable if any of the chosen outputs differs from the fault free run.
+ */
+use std::fmt;
+
+use super::*;
+
+const BITS: u8 = 16;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Fault {
+    StuckAt0,
+    StuckAt1,
+    BitFlip(u8),
+}
+
+impl Fault {
+    fn all() -> Vec<Fault> {
+        let mut faults = vec![Fault::StuckAt0, Fault::StuckAt1];
+        faults.extend((0..BITS).map(Fault::BitFlip));
+        faults
+    }
+
+    fn apply(&self, value: SlotValue) -> SlotValue {
+        match self {
+            Fault::StuckAt0 => 0,
+            Fault::StuckAt1 => SlotValue::MAX,
+            Fault::BitFlip(bit) => value ^ (1 << bit),
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::StuckAt0 => write!(f, "stuck-at-0"),
+            Fault::StuckAt1 => write!(f, "stuck-at-1"),
+            Fault::BitFlip(bit) => write!(f, "bit-flip-{}", bit),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FaultResult {
+    pub wire: String,
+    pub fault: Fault,
+    // outputs whose value differs from the fault free run
+    pub observed_on: Vec<String>,
+}
+
+impl FaultResult {
+    pub fn observable(&self) -> bool {
+        !self.observed_on.is_empty()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FaultReport {
+    pub outputs: Vec<String>,
+    pub results: Vec<FaultResult>,
+}
+
+impl FaultReport {
+    pub fn nobservable(&self) -> usize {
+        self.results.iter().filter(|x| x.observable()).count()
+    }
+
+    pub fn coverage(&self) -> f64 {
+        if self.results.is_empty() {
+            return 0.0;
+        }
+        self.nobservable() as f64 / self.results.len() as f64
+    }
+}
+
+impl fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "outputs: {}", self.outputs.join(", "))?;
+        writeln!(
+            f,
+            "coverage: {}/{} faults observable ({:.1}%)",
+            self.nobservable(),
+            self.results.len(),
+            self.coverage() * 100.0
+        )?;
+        writeln!(f, "unobservable:")?;
+        for result in self.results.iter().filter(|x| !x.observable()) {
+            writeln!(f, "  {} {}", result.wire, result.fault)?;
+        }
+        Ok(())
+    }
+}
+
+impl Circuit {
+    /*
+     * Injects every fault into every wire, including the primary inputs, and reruns the circuit.
+     * The `vector` overrides are applied to the fault free as well as the faulty runs, which
+     * allows evaluating how well a given test vector covers the circuit.
+     */
+    pub fn fault_coverage(&self, vector: &Slots, outputs: &[&str]) -> FaultReport {
+        let golden = self.run_with_slots(vector.clone());
+        let faults = Fault::all();
+        let mut results = Vec::new();
+
+        let assignees = self.statements.iter().map(|stmt| stmt.assignee().clone());
+        for wire in self.input_wires().into_iter().chain(assignees) {
+            let value = Circuit::resolve_val(&golden, &Slot(wire.clone()));
+            for fault in &faults {
+                let mut slots = vector.clone();
+                slots.insert(wire.clone(), fault.apply(value));
+                let slots = self.run_with_slots(slots);
+                let observed_on = outputs
+                    .iter()
+                    .filter(|&&output| slots.get(output) != golden.get(output))
+                    .map(|x| x.to_string())
+                    .collect();
+                results.push(FaultResult {
+                    wire: wire.clone(),
+                    fault: *fault,
+                    observed_on,
+                });
+            }
+        }
+
+        FaultReport {
+            outputs: outputs.iter().map(|x| x.to_string()).collect(),
+            results,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PROGRAM: &str = "\
+        \x20123 -> x\n\
+        \x20x AND 1 -> o\
+        ";
+
+    const SAMPLE_FAULT_PROGRAM: &str = "\
+        \x20123 -> x\n\
+        \x20456 -> y\n\
+        \x20x AND y -> d\n\
+        \x20NOT x -> h\
+        ";
+
+    fn observable(report: &FaultReport, wire: &str) -> Vec<Fault> {
+        report
+            .results
+            .iter()
+            .filter(|x| x.wire == wire && x.observable())
+            .map(|x| x.fault)
+            .collect()
+    }
+
+    #[test]
+    fn masked_faults() {
+        let circuit = Circuit::from_program(true, PROGRAM);
+        let report = circuit.fault_coverage(&HashMap::new(), &["o"]);
+        assert_eq!(report.results.len(), 2 * 18);
+        assert_eq!(
+            observable(&report, "x"),
+            vec![Fault::StuckAt0, Fault::BitFlip(0)]
+        );
+        assert_eq!(observable(&report, "o").len(), 18);
+        assert_eq!(report.nobservable(), 20);
+    }
+
+    #[test]
+    fn test_vector_changes_coverage() {
+        let circuit = Circuit::from_program(true, PROGRAM);
+        let mut vector = HashMap::new();
+        vector.insert("x".to_string(), 0);
+        let report = circuit.fault_coverage(&vector, &["o"]);
+        assert_eq!(
+            observable(&report, "x"),
+            vec![Fault::StuckAt1, Fault::BitFlip(0)]
+        );
+    }
+
+    #[test]
+    fn faults_on_inputs() {
+        let circuit = Circuit::from_program(true, "x AND 1 -> o");
+        let mut vector = HashMap::new();
+        vector.insert("x".to_string(), 1);
+        let report = circuit.fault_coverage(&vector, &["o"]);
+        assert_eq!(report.results.len(), 2 * 18);
+        assert_eq!(
+            observable(&report, "x"),
+            vec![Fault::StuckAt0, Fault::BitFlip(0)]
+        );
+    }
+
+    #[test]
+    fn observed_on_lists_outputs() {
+        let circuit = Circuit::from_program(true, SAMPLE_FAULT_PROGRAM);
+        let report = circuit.fault_coverage(&HashMap::new(), &["d", "h"]);
+        let result = report
+            .results
+            .iter()
+            .find(|x| x.wire == "x" && x.fault == Fault::StuckAt0)
+            .unwrap();
+        assert_eq!(result.observed_on, vec!["d", "h"]);
+        let result = report
+            .results
+            .iter()
+            .find(|x| x.wire == "y" && x.fault == Fault::StuckAt0)
+            .unwrap();
+        assert_eq!(result.observed_on, vec!["d"]);
+    }
+}
@@ -0,0 +1,350 @@
+/*
+ * Differential fuzzing of the circuit evaluation.
+ *
+ * Random acyclic netlists are generated from a seed, rendered into program text with shuffled
+ * lines and then evaluated by `Circuit::run_with_slots` as well as by a naive recursive evaluator
+ * which works directly on the generated netlist and shares no code with the circuit.
+ * The same netlist is also wrapped into a module and instantiated to check the flattening.
+ * Failures report the seed, so they can be reproduced via `check_seed`.
+ */
+use super::*;
+
+// xorshift64*, good enough to generate test programs and keeps us free of dependencies
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn value(&mut self) -> SlotValue {
+        self.next() as SlotValue
+    }
+
+    // Mostly amounts within the signal width, sometimes ones that shift out every bit
+    fn shift(&mut self) -> SlotValue {
+        match self.chance(10) {
+            true => 16 + self.below(SlotValue::MAX as usize - 15) as SlotValue,
+            false => self.below(16) as SlotValue,
+        }
+    }
+
+    fn shuffle<T>(&mut self, xs: &mut [T]) {
+        for i in (1..xs.len()).rev() {
+            let j = self.below(i + 1);
+            xs.swap(i, j);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Wire(usize),
+    Lit(SlotValue),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Gate {
+    Assign(Operand),
+    Not(Operand),
+    And(Operand, Operand),
+    Or(Operand, Operand),
+    LShift(Operand, SlotValue),
+    RShift(Operand, SlotValue),
+}
+
+// relative weights of each gate kind
+struct GateMix {
+    assign: usize,
+    not: usize,
+    and: usize,
+    or: usize,
+    lshift: usize,
+    rshift: usize,
+}
+
+impl Default for GateMix {
+    fn default() -> Self {
+        Self {
+            assign: 2,
+            not: 2,
+            and: 3,
+            or: 3,
+            lshift: 1,
+            rshift: 1,
+        }
+    }
+}
+
+struct NetlistConfig {
+    nwires: usize,
+    // percent of operands that are literals instead of wires
+    literals: usize,
+    mix: GateMix,
+}
+
+struct Netlist {
+    gates: Vec<Gate>,
+}
+
+fn wire_name(mut idx: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'a' + (idx % 26) as u8);
+        idx /= 26;
+        if idx == 0 {
+            break;
+        }
+        idx -= 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap()
+}
+
+impl Netlist {
+    // Each gate only references wires created before it, which keeps the netlist acyclic
+    fn generate(rng: &mut Rng, config: &NetlistConfig) -> Self {
+        let mix = &config.mix;
+        let weights = [mix.assign, mix.not, mix.and, mix.or, mix.lshift, mix.rshift];
+        let total: usize = weights.iter().sum();
+
+        let mut gates = Vec::with_capacity(config.nwires);
+        for idx in 0..config.nwires {
+            let operand = |rng: &mut Rng| {
+                if idx == 0 || rng.chance(config.literals) {
+                    Operand::Lit(rng.value())
+                } else {
+                    Operand::Wire(rng.below(idx))
+                }
+            };
+            let mut pick = rng.below(total);
+            let kind = weights
+                .iter()
+                .position(|&w| {
+                    if pick < w {
+                        true
+                    } else {
+                        pick -= w;
+                        false
+                    }
+                })
+                .unwrap();
+            let gate = match kind {
+                0 => Gate::Assign(operand(rng)),
+                1 => Gate::Not(operand(rng)),
+                2 => Gate::And(operand(rng), operand(rng)),
+                3 => Gate::Or(operand(rng), operand(rng)),
+                4 => Gate::LShift(operand(rng), rng.shift()),
+                _ => Gate::RShift(operand(rng), rng.shift()),
+            };
+            gates.push(gate);
+        }
+        Self { gates }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let operand = |op: &Operand| match op {
+            Operand::Wire(idx) => wire_name(*idx),
+            Operand::Lit(x) => x.to_string(),
+        };
+        self.gates
+            .iter()
+            .enumerate()
+            .map(|(idx, gate)| {
+                let expr = match gate {
+                    Gate::Assign(x) => operand(x),
+                    Gate::Not(x) => format!("NOT {}", operand(x)),
+                    Gate::And(x, y) => format!("{} AND {}", operand(x), operand(y)),
+                    Gate::Or(x, y) => format!("{} OR {}", operand(x), operand(y)),
+                    Gate::LShift(x, n) => format!("{} LSHIFT {}", operand(x), n),
+                    Gate::RShift(x, n) => format!("{} RSHIFT {}", operand(x), n),
+                };
+                format!("{} -> {}", expr, wire_name(idx))
+            })
+            .collect()
+    }
+
+    fn eval(&self, overrides: &HashMap<usize, SlotValue>) -> Vec<SlotValue> {
+        let mut memo = vec![None; self.gates.len()];
+        (0..self.gates.len())
+            .map(|idx| self.eval_wire(idx, overrides, &mut memo))
+            .collect()
+    }
+
+    fn eval_wire(
+        &self,
+        idx: usize,
+        overrides: &HashMap<usize, SlotValue>,
+        memo: &mut Vec<Option<SlotValue>>,
+    ) -> SlotValue {
+        if let Some(x) = overrides.get(&idx) {
+            return *x;
+        }
+        if let Some(x) = memo[idx] {
+            return x;
+        }
+        let mut operand = |op: &Operand| match op {
+            Operand::Wire(idx) => self.eval_wire(*idx, overrides, memo),
+            Operand::Lit(x) => *x,
+        };
+        let value = match &self.gates[idx] {
+            Gate::Assign(x) => operand(x),
+            Gate::Not(x) => !operand(x),
+            Gate::And(x, y) => operand(x) & operand(y),
+            Gate::Or(x, y) => operand(x) | operand(y),
+            Gate::LShift(_, n) | Gate::RShift(_, n) if *n >= 16 => 0,
+            Gate::LShift(x, n) => operand(x) << n,
+            Gate::RShift(x, n) => operand(x) >> n,
+        };
+        memo[idx] = Some(value);
+        value
+    }
+}
+
+fn check_seed(seed: u64, config: &NetlistConfig) {
+    let mut rng = Rng::new(seed);
+    let netlist = Netlist::generate(&mut rng, config);
+    let mut lines = netlist.lines();
+    rng.shuffle(&mut lines);
+    let program = lines.join("\n");
+
+    let mut overrides = HashMap::new();
+    for _ in 0..rng.below(4) {
+        overrides.insert(rng.below(config.nwires), rng.value());
+    }
+    let slots: Slots = overrides
+        .iter()
+        .map(|(idx, x)| (wire_name(*idx), *x))
+        .collect();
+    let expected = netlist.eval(&overrides);
+
+    let circuit = Circuit::from_program(true, &program);
+    let actual = circuit.run_with_slots(slots.clone());
+    for (idx, x) in expected.iter().enumerate() {
+        let wire = wire_name(idx);
+        assert_eq!(
+            actual.get(&wire),
+            Some(x),
+            "seed {} wire '{}' differs for program:\n{}",
+            seed,
+            wire,
+            program
+        );
+    }
+
+    // All wires become module outputs which are bound to prefixed wires at the instance
+    let wires: Vec<String> = (0..config.nwires).map(wire_name).collect();
+    let outputs: Vec<String> = wires.iter().map(|x| format!("out_{}", x)).collect();
+    let program = format!(
+        "module net() -> ({}) {{\n{}\n}}\nnet() -> ({})",
+        wires.join(", "),
+        program,
+        outputs.join(", ")
+    );
+    let slots: Slots = slots
+        .into_iter()
+        .map(|(wire, x)| (format!("out_{}", wire), x))
+        .collect();
+    let circuit = Circuit::from_program(true, &program);
+    let actual = circuit.run_with_slots(slots);
+    for (idx, x) in expected.iter().enumerate() {
+        assert_eq!(
+            actual.get(&outputs[idx]),
+            Some(x),
+            "seed {} flattened wire '{}' differs",
+            seed,
+            outputs[idx]
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wire_names() {
+        assert_eq!(wire_name(0), "a");
+        assert_eq!(wire_name(25), "z");
+        assert_eq!(wire_name(26), "aa");
+        assert_eq!(wire_name(27), "ab");
+        assert_eq!(wire_name(26 * 27), "aaa");
+    }
+
+    #[test]
+    fn small_netlists() {
+        for seed in 0..100 {
+            let config = NetlistConfig {
+                nwires: 1 + seed as usize % 12,
+                literals: 20,
+                mix: GateMix::default(),
+            };
+            check_seed(seed, &config);
+        }
+    }
+
+    #[test]
+    fn large_netlists() {
+        for seed in 0..20 {
+            let config = NetlistConfig {
+                nwires: 200,
+                literals: 10,
+                mix: GateMix::default(),
+            };
+            check_seed(seed, &config);
+        }
+    }
+
+    #[test]
+    fn shift_heavy_netlists() {
+        for seed in 0..40 {
+            let config = NetlistConfig {
+                nwires: 30,
+                literals: 30,
+                mix: GateMix {
+                    assign: 1,
+                    not: 1,
+                    and: 0,
+                    or: 0,
+                    lshift: 4,
+                    rshift: 4,
+                },
+            };
+            check_seed(seed, &config);
+        }
+    }
+
+    #[test]
+    fn wiring_only_netlists() {
+        for seed in 0..40 {
+            let config = NetlistConfig {
+                nwires: 40,
+                literals: 5,
+                mix: GateMix {
+                    assign: 1,
+                    not: 0,
+                    and: 0,
+                    or: 0,
+                    lshift: 0,
+                    rshift: 0,
+                },
+            };
+            check_seed(seed, &config);
+        }
+    }
+}
@@ -1,13 +1,36 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt, process,
+};
 
+use modules::Module;
 use regex::Regex;
 use Operation::*;
 use Stmt::*;
 use Value::*;
 
+mod cli;
+mod diff;
+mod faults;
+#[cfg(test)]
+mod fuzz;
+mod modules;
+
 fn main() {
+    if env::args().len() > 1 {
+        let result = cli::Options::from_args(env::args().skip(1)).and_then(cli::run);
+        match result {
+            Ok(output) => print!("{}", output),
+            Err(err) => {
+                eprintln!("{}\n\n{}", err, cli::USAGE);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
     let program = include_str!("./input.txt");
-    let circuit = Circuit::from_program(true, &program);
+    let circuit = Circuit::from_program(true, program);
     let slots = circuit.run();
 
     let a_part1 = slots.get("a").unwrap();
@@ -39,6 +62,15 @@ impl From<&str> for Value {
     }
 }
 
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal(x) => write!(f, "{}", x),
+            Slot(s) => write!(f, "{}", s),
+        }
+    }
+}
+
 impl Value {
     fn reference(&self) -> Option<String> {
         match self {
@@ -70,15 +102,22 @@ impl From<&str> for Operation {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-struct Dependencies(Vec<String>);
-
-impl Default for Dependencies {
-    fn default() -> Self {
-        Self(Vec::new())
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            And => "AND",
+            Or => "OR",
+            LShift => "LSHIFT",
+            RShift => "RSHIFT",
+            Not => "NOT",
+        };
+        write!(f, "{}", op)
     }
 }
 
+#[derive(Debug, PartialEq, Clone, Default)]
+struct Dependencies(Vec<String>);
+
 impl Dependencies {
     fn from_values(values: Vec<&Value>) -> Self {
         let deps: Vec<String> = values
@@ -99,11 +138,45 @@ enum Stmt {
     SetNot(String, Value, Dependencies),
 }
 
+impl Stmt {
+    fn assignee(&self) -> &String {
+        match self {
+            Assign(assignee, ..) => assignee,
+            AssignExpr(assignee, ..) => assignee,
+            SetNot(assignee, ..) => assignee,
+        }
+    }
+
+    fn dependencies(&self) -> &Dependencies {
+        match self {
+            Assign(_, _, deps) => deps,
+            AssignExpr(_, _, _, _, deps) => deps,
+            SetNot(_, _, deps) => deps,
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Assign(assignee, val, _) => write!(f, "{} -> {}", val, assignee),
+            AssignExpr(assignee, val1, op, val2, _) => {
+                write!(f, "{} {} {} -> {}", val1, op, val2, assignee)
+            }
+            SetNot(assignee, val, _) => write!(f, "NOT {} -> {}", val, assignee),
+        }
+    }
+}
+
 struct Circuit {
     statements: Vec<Stmt>,
     stmt_assign: Regex,
     stmt_expr_assign: Regex,
     stmt_not: Regex,
+    stmt_instance: Regex,
+    module_header: Regex,
+    modules: HashMap<String, Module>,
+    instance_counts: HashMap<String, usize>,
     halt_and_catch_fire: bool,
 }
 
@@ -113,20 +186,32 @@ impl Circuit {
         let stmt_expr_assign: Regex =
             Regex::new(r"^([^\W]+) (AND|OR|LSHIFT|RSHIFT) ([^\W]+) -> ([^\W]+)").unwrap();
         let stmt_not: Regex = Regex::new(r"^NOT ([^\W]+) -> ([^\W]+)").unwrap();
+        let stmt_instance: Regex =
+            Regex::new(r"^([^\W]+)\s*\(([^)]*)\)\s*->\s*\(([^)]*)\)$").unwrap();
+        let module_header: Regex =
+            Regex::new(r"^module\s+([^\W]+)\s*\(([^)]*)\)\s*->\s*\(([^)]*)\)\s*\{$").unwrap();
         Self {
             statements: vec![],
             stmt_assign,
             stmt_expr_assign,
             stmt_not,
+            stmt_instance,
+            module_header,
+            modules: HashMap::new(),
+            instance_counts: HashMap::new(),
             halt_and_catch_fire,
         }
     }
 
     fn from_program(halt_and_catch_fire: bool, program: &str) -> Self {
+        Circuit::parse(halt_and_catch_fire, program).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn parse(halt_and_catch_fire: bool, program: &str) -> Result<Self, String> {
         let mut circuit = Circuit::new(halt_and_catch_fire);
-        circuit.add_statements(&program);
-        circuit.sort_statements();
-        circuit
+        circuit.add_statements(program)?;
+        circuit.sort_statements()?;
+        Ok(circuit)
     }
 
     /*
@@ -137,23 +222,29 @@ impl Circuit {
 
     * An alternative would be to focus on a value we need to resolve and walk its dependencies
     * backwards. However we'd have to do this for each value
+
+    * Wires that no statement assigns are treated as inputs which need to be provided via
+    * `run_with_slots`.
     */
-    fn sort_statements(&mut self) {
+    fn sort_statements(&mut self) -> Result<(), String> {
         let mut stmts = self.statements.clone();
         let mut sorted = Vec::<Stmt>::with_capacity(stmts.len());
-        let mut resolved_symbols = HashSet::<String>::new();
+        let assignees: HashSet<&String> = stmts.iter().map(|stmt| stmt.assignee()).collect();
+        let mut resolved_symbols: HashSet<String> = stmts
+            .iter()
+            .flat_map(|stmt| stmt.dependencies().0.iter())
+            .filter(|dep| !assignees.contains(dep))
+            .cloned()
+            .collect();
 
-        while stmts.len() > 0 {
+        while !stmts.is_empty() {
+            let nremaining = stmts.len();
             let remaining: Vec<Stmt> = stmts
                 .into_iter()
                 .filter_map(|stmt| {
-                    let (assignee, deps) = match &stmt {
-                        Assign(assignee, _, deps) => (assignee, &deps.0),
-                        AssignExpr(assignee, _, _, _, deps) => (assignee, &deps.0),
-                        SetNot(assignee, _, deps) => (assignee, &deps.0),
-                    };
+                    let (assignee, deps) = (stmt.assignee(), &stmt.dependencies().0);
 
-                    let all_deps_resolved = deps.iter().all(|s| resolved_symbols.get(s).is_some());
+                    let all_deps_resolved = deps.iter().all(|s| resolved_symbols.contains(s));
 
                     if all_deps_resolved {
                         resolved_symbols.insert(assignee.clone());
@@ -164,15 +255,68 @@ impl Circuit {
                     }
                 })
                 .collect();
+            if remaining.len() == nremaining {
+                return Err(format!(
+                    "Circuit contains a cycle involving '{}'",
+                    remaining[0].assignee()
+                ));
+            }
             stmts = remaining;
         }
 
         self.statements = sorted;
+        Ok(())
+    }
+
+    // The input wires which have no value in `slots`, running with them missing would panic
+    // Wires which are used but never assigned, i.e. the ones set with `--set`
+    fn input_wires(&self) -> Vec<String> {
+        let assignees: HashSet<&String> =
+            self.statements.iter().map(|stmt| stmt.assignee()).collect();
+        let mut inputs: Vec<String> = self
+            .statements
+            .iter()
+            .flat_map(|stmt| stmt.dependencies().0.iter())
+            .filter(|dep| !assignees.contains(dep))
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        inputs.sort();
+        inputs
+    }
+
+    fn missing_inputs(&self, slots: &Slots) -> Vec<String> {
+        self.input_wires()
+            .into_iter()
+            .filter(|wire| !slots.contains_key(wire))
+            .collect()
+    }
+
+    fn has_wire(&self, wire: &str) -> bool {
+        self.statements.iter().any(|stmt| {
+            stmt.assignee() == wire || stmt.dependencies().0.iter().any(|dep| dep == wire)
+        })
     }
 
     fn run_with_slots(&self, mut slots: Slots) -> Slots {
         for stmt in &self.statements {
-            Circuit::process_stmt(&mut slots, &stmt)
+            Circuit::process_stmt(&mut slots, stmt)
+        }
+        slots
+    }
+
+    /*
+     * Like `run_with_slots` but wires depending on a wire that is never assigned are left
+     * without a value instead of panicking.
+     */
+    fn run_partial(&self, mut slots: Slots) -> Slots {
+        for stmt in &self.statements {
+            if !slots.contains_key(stmt.assignee()) {
+                if let Some(value) = Circuit::eval_stmt(&slots, stmt) {
+                    slots.insert(stmt.assignee().clone(), value);
+                }
+            }
         }
         slots
     }
@@ -181,14 +325,15 @@ impl Circuit {
         self.run_with_slots(HashMap::new())
     }
 
-    fn add_statements(&mut self, program: &str) {
-        let lines = program.lines();
+    fn add_statements(&mut self, program: &str) -> Result<(), String> {
+        let lines = self.extract_modules(program)?;
         for line in lines {
-            self.add_statement(line);
+            self.add_statement(line)?;
         }
+        Ok(())
     }
 
-    fn add_statement(&mut self, line: &str) {
+    fn add_statement(&mut self, line: &str) -> Result<(), String> {
         let line = line.trim();
         if let Some(stmt) = self.stmt_assign(line) {
             self.statements.push(stmt);
@@ -196,9 +341,12 @@ impl Circuit {
             self.statements.push(stmt);
         } else if let Some(stmt) = self.stmt_not(line) {
             self.statements.push(stmt);
+        } else if let Some(stmts) = self.stmt_instance(line)? {
+            self.statements.extend(stmts);
         } else if self.halt_and_catch_fire {
-            panic!("Unable to add '{}'", line);
+            return Err(format!("Unable to add '{}'", line));
         }
+        Ok(())
     }
 
     fn stmt_assign(&self, line: &str) -> Option<Stmt> {
@@ -270,37 +418,58 @@ impl Circuit {
         match stmt {
             Assign(assignee, val, ..) => {
                 if slots.get(assignee).is_none() {
-                    slots.insert(assignee.clone(), Circuit::resolve_val(&slots, val));
+                    slots.insert(assignee.clone(), Circuit::resolve_val(slots, val));
                 }
             }
             AssignExpr(assignee, val1, op, val2, ..) => {
                 if slots.get(assignee).is_none() {
-                    let val1 = Circuit::resolve_val(&slots, val1);
-                    let val2 = Circuit::resolve_val(&slots, val2);
-                    let result = match op {
-                        And => val1 & val2,
-                        Or => val1 | val2,
-                        LShift => val1 << val2,
-                        RShift => val1 >> val2,
-                        Not => panic!("NOT is not part of an AssignExpr"),
-                    };
-                    slots.insert(assignee.clone(), result);
+                    let val1 = Circuit::resolve_val(slots, val1);
+                    let val2 = Circuit::resolve_val(slots, val2);
+                    slots.insert(assignee.clone(), Circuit::apply(op, val1, val2));
                 }
             }
             SetNot(assignee, val, ..) => {
                 if slots.get(assignee).is_none() {
-                    slots.insert(assignee.clone(), !Circuit::resolve_val(&slots, val));
+                    slots.insert(assignee.clone(), !Circuit::resolve_val(slots, val));
                 }
             }
         }
     }
 
+    /*
+     * Computes the value of `stmt` unless one of its operands has no value in `slots`.
+     */
+    fn eval_stmt(slots: &Slots, stmt: &Stmt) -> Option<SlotValue> {
+        let lookup = |val: &Value| match val {
+            Literal(x) => Some(*x),
+            Slot(key) => slots.get(key).copied(),
+        };
+        match stmt {
+            Assign(_, val, ..) => lookup(val),
+            AssignExpr(_, val1, op, val2, ..) => {
+                Some(Circuit::apply(op, lookup(val1)?, lookup(val2)?))
+            }
+            SetNot(_, val, ..) => lookup(val).map(|x| !x),
+        }
+    }
+
+    fn apply(op: &Operation, val1: SlotValue, val2: SlotValue) -> SlotValue {
+        match op {
+            And => val1 & val2,
+            Or => val1 | val2,
+            // shifting by the signal width or more shifts out every bit
+            LShift => val1.checked_shl(val2 as u32).unwrap_or(0),
+            RShift => val1.checked_shr(val2 as u32).unwrap_or(0),
+            Not => panic!("NOT is not part of an AssignExpr"),
+        }
+    }
+
     fn resolve_val(slots: &Slots, val: &Value) -> SlotValue {
         match val {
             Literal(x) => *x,
             Slot(key) => *slots
                 .get(key)
-                .expect(&format!("Unable to resolve slot {:?}", &key)),
+                .unwrap_or_else(|| panic!("Unable to resolve slot {:?}", &key)),
         }
     }
 }
@@ -466,6 +635,13 @@ mod test {
         );
     }
 
+    #[test]
+    fn shift_out_all_bits() {
+        let circuit = Circuit::from_program(true, "1 LSHIFT 20 -> a\n65535 RSHIFT 16 -> b");
+        let slots = circuit.run();
+        assert_eq!(slots, map!("a" => 0, "b" => 0));
+    }
+
     #[test]
     fn run_program_unordered() {
         let circuit = Circuit::from_program(true, SAMPLE_PROGRAM_UNORDERED);
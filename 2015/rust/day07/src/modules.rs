@@ -0,0 +1,486 @@
+/*
+ * Named sub-circuits which are flattened into ordinary statements.
+ *
+ *   module half_add(a, b) -> (s, c) {
+ *       a AND b -> c
+ *       a OR b -> o
+ *       NOT c -> n
+ *       o AND n -> s
+ *   }
+ *   half_add(x, y) -> (s0, c0)
+ *
+ * Module inputs and outputs are replaced with the values and wires given at the instantiation.
+ * All other wires of the module body are prefixed with the instance path, i.e. `half_add.0.o`.
+ * Nested instances extend that path, i.e. `full_add.0.half_add.1.o`.
+ * Since wires in the program text only consist of word characters those generated names never
+ * clash with wires defined by the user.
+ */
+use super::*;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Module {
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    body: Vec<String>,
+}
+
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+fn rename_stmt(stmt: &Stmt, assignee: String, rename: &dyn Fn(&Value) -> Value) -> Stmt {
+    match stmt {
+        Assign(_, val, _) => {
+            let val = rename(val);
+            let dependencies = Dependencies::from_values(vec![&val]);
+            Assign(assignee, val, dependencies)
+        }
+        AssignExpr(_, op_left, op, op_right, _) => {
+            let op_left = rename(op_left);
+            let op_right = rename(op_right);
+            let dependencies = Dependencies::from_values(vec![&op_left, &op_right]);
+            AssignExpr(assignee, op_left, op.clone(), op_right, dependencies)
+        }
+        SetNot(_, val, _) => {
+            let val = rename(val);
+            let dependencies = Dependencies::from_values(vec![&val]);
+            SetNot(assignee, val, dependencies)
+        }
+    }
+}
+
+fn is_wire_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+impl Circuit {
+    /*
+     * Removes all module definitions from the program and registers them with the circuit.
+     * Modules are collected before any statement is added so that they can be instantiated
+     * before they are defined.
+     */
+    pub fn extract_modules<'a>(&mut self, program: &'a str) -> Result<Vec<&'a str>, String> {
+        let mut lines = Vec::new();
+        let mut current: Option<(String, Module)> = None;
+
+        for line in program.lines() {
+            let trimmed = line.trim();
+            match current.as_mut() {
+                Some(_) if trimmed == "}" => {
+                    let (name, module) = current.take().expect("current module");
+                    self.validate_module(&name, &module)?;
+                    if self.modules.insert(name.clone(), module).is_some() {
+                        return Err(format!("Module '{}' is defined more than once", name));
+                    }
+                }
+                Some((name, _)) if self.module_header.is_match(trimmed) => {
+                    return Err(format!("Cannot define a module inside module '{}'", name));
+                }
+                Some((_, module)) => {
+                    if !trimmed.is_empty() {
+                        module.body.push(trimmed.to_string());
+                    }
+                }
+                None => match self.module_header.captures(trimmed) {
+                    Some(captures) => {
+                        let (name, inputs, outputs) = (
+                            captures.get(1).expect("capture 1").as_str(),
+                            captures.get(2).expect("capture 2").as_str(),
+                            captures.get(3).expect("capture 3").as_str(),
+                        );
+                        let module = Module {
+                            inputs: parse_list(inputs),
+                            outputs: parse_list(outputs),
+                            body: vec![],
+                        };
+                        current = Some((name.to_string(), module));
+                    }
+                    None => lines.push(line),
+                },
+            }
+        }
+
+        if let Some((name, _)) = current {
+            return Err(format!("Module '{}' is missing its closing '}}'", name));
+        }
+        Ok(lines)
+    }
+
+    // The wires assigned by a line of a module body, None if the line is no statement or instance
+    fn body_assignees(&self, line: &str) -> Option<Vec<String>> {
+        if let Some(captures) = self.stmt_instance.captures(line) {
+            return Some(parse_list(captures.get(3).expect("capture 3").as_str()));
+        }
+        self.stmt_assign(line)
+            .or_else(|| self.stmt_assign_expr(line))
+            .or_else(|| self.stmt_not(line))
+            .map(|stmt| vec![stmt.assignee().clone()])
+    }
+
+    /*
+     * Checks the declared wires of a module against its body, so mistakes are reported where the
+     * module is defined instead of surfacing as unresolved wires once the circuit runs.
+     */
+    fn validate_module(&self, name: &str, module: &Module) -> Result<(), String> {
+        let declared: Vec<&String> = module.inputs.iter().chain(&module.outputs).collect();
+        for (idx, wire) in declared.iter().enumerate() {
+            if !is_wire_name(wire) {
+                return Err(format!(
+                    "Module '{}' declares invalid wire '{}'",
+                    name, wire
+                ));
+            }
+            if declared[..idx].contains(wire) {
+                return Err(format!(
+                    "Module '{}' declares '{}' more than once",
+                    name, wire
+                ));
+            }
+        }
+
+        let mut assigned = HashSet::new();
+        for line in &module.body {
+            match self.body_assignees(line) {
+                Some(wires) => assigned.extend(wires),
+                None if self.halt_and_catch_fire => {
+                    return Err(format!("Unable to add '{}' in module '{}'", line, name))
+                }
+                None => {}
+            }
+        }
+        if let Some(input) = module.inputs.iter().find(|x| assigned.contains(*x)) {
+            return Err(format!(
+                "Module '{}' assigns to its input '{}'",
+                name, input
+            ));
+        }
+        match module.outputs.iter().find(|x| !assigned.contains(*x)) {
+            Some(output) => Err(format!(
+                "Module '{}' never assigns its output '{}'",
+                name, output
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /*
+     * Splits the arguments and outputs of an instance of a known module. Arguments are wires or
+     * literals, outputs need to be wires since they get assigned.
+     */
+    fn instance_wires(
+        &self,
+        name: &str,
+        args: &str,
+        outputs: &str,
+    ) -> Result<(Vec<String>, Vec<String>), String> {
+        let (args, outputs) = (parse_list(args), parse_list(outputs));
+        if !self.modules.contains_key(name) {
+            return Ok((args, outputs));
+        }
+        if let Some(arg) = args.iter().find(|x| !is_wire_name(x)) {
+            return Err(format!(
+                "Instance of module '{}' has invalid input '{}'",
+                name, arg
+            ));
+        }
+        let is_output = |wire: &String| is_wire_name(wire) && wire.parse::<SlotValue>().is_err();
+        if let Some(output) = outputs.iter().find(|x| !is_output(x)) {
+            return Err(format!(
+                "Instance of module '{}' has invalid output '{}'",
+                name, output
+            ));
+        }
+        Ok((args, outputs))
+    }
+
+    pub fn stmt_instance(&mut self, line: &str) -> Result<Option<Vec<Stmt>>, String> {
+        // half_add(x, y) -> (s0, c0)
+        let captures = match self.stmt_instance.captures(line) {
+            Some(captures) => captures,
+            None => return Ok(None),
+        };
+        let (name, args, outputs) = (
+            captures.get(1).expect("capture 1").as_str(),
+            captures.get(2).expect("capture 2").as_str(),
+            captures.get(3).expect("capture 3").as_str(),
+        );
+        let (args, outputs) = self.instance_wires(name, args, outputs)?;
+        let args: Vec<Value> = args.iter().map(|x| x.as_str().into()).collect();
+        self.instantiate("", name, args, outputs, &mut vec![])
+    }
+
+    fn instantiate(
+        &mut self,
+        parent: &str,
+        name: &str,
+        args: Vec<Value>,
+        outputs: Vec<String>,
+        stack: &mut Vec<String>,
+    ) -> Result<Option<Vec<Stmt>>, String> {
+        let module = match self.modules.get(name) {
+            Some(module) => module.clone(),
+            None => return Ok(None),
+        };
+        if module.inputs.len() != args.len() || module.outputs.len() != outputs.len() {
+            return Err(format!(
+                "Module '{}' takes {} inputs and {} outputs, but was given {} and {}",
+                name,
+                module.inputs.len(),
+                module.outputs.len(),
+                args.len(),
+                outputs.len()
+            ));
+        }
+        if stack.iter().any(|x| x == name) {
+            return Err(format!("Module '{}' instantiates itself", name));
+        }
+
+        let key = format!("{}{}", parent, name);
+        let count = self.instance_counts.entry(key.clone()).or_insert(0);
+        let path = format!("{}.{}", key, count);
+        *count += 1;
+
+        let rename_wire = |wire: &str| -> Value {
+            if let Some(idx) = module.inputs.iter().position(|x| x == wire) {
+                args[idx].clone()
+            } else if let Some(idx) = module.outputs.iter().position(|x| x == wire) {
+                Slot(outputs[idx].clone())
+            } else {
+                Slot(format!("{}.{}", path, wire))
+            }
+        };
+        let rename = |val: &Value| -> Value {
+            match val {
+                Literal(_) => val.clone(),
+                Slot(wire) => rename_wire(wire),
+            }
+        };
+        // `validate_module` made sure that no input is assigned
+        let rename_assignee = |wire: &str| -> String {
+            rename_wire(wire)
+                .reference()
+                .expect("non-input wires always resolve to a slot")
+        };
+
+        stack.push(name.to_string());
+        let mut stmts = Vec::new();
+        for line in &module.body {
+            if let Some(captures) = self.stmt_instance.captures(line) {
+                let (inner, inner_args, inner_outputs) = (
+                    captures.get(1).expect("capture 1").as_str(),
+                    captures.get(2).expect("capture 2").as_str(),
+                    captures.get(3).expect("capture 3").as_str(),
+                );
+                let (inner_args, inner_outputs) =
+                    self.instance_wires(inner, inner_args, inner_outputs)?;
+                let inner_args: Vec<Value> = inner_args
+                    .iter()
+                    .map(|x| rename(&x.as_str().into()))
+                    .collect();
+                let inner_outputs: Vec<String> =
+                    inner_outputs.iter().map(|x| rename_assignee(x)).collect();
+                let parent = format!("{}.", path);
+                match self.instantiate(&parent, inner, inner_args, inner_outputs, stack)? {
+                    Some(inner_stmts) => stmts.extend(inner_stmts),
+                    None if self.halt_and_catch_fire => {
+                        return Err(format!(
+                            "Unknown module '{}' used in module '{}'",
+                            inner, name
+                        ))
+                    }
+                    None => {}
+                }
+                continue;
+            }
+
+            let stmt = self
+                .stmt_assign(line)
+                .or_else(|| self.stmt_assign_expr(line))
+                .or_else(|| self.stmt_not(line));
+            if let Some(stmt) = stmt {
+                let assignee = rename_assignee(stmt.assignee());
+                stmts.push(rename_stmt(&stmt, assignee, &rename));
+            }
+        }
+        stack.pop();
+        Ok(Some(stmts))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HALF_ADD: &str = "\
+        \x20module half_add(a, b) -> (s, c) {\n\
+        \x20    a AND b -> c\n\
+        \x20    a OR b -> o\n\
+        \x20    NOT c -> n\n\
+        \x20    o AND n -> s\n\
+        \x20}\n\
+        ";
+
+    fn wire(slots: &Slots, name: &str) -> SlotValue {
+        *slots.get(name).expect(name)
+    }
+
+    #[test]
+    fn flatten_half_add() {
+        let program = format!(
+            "{}\
+            \x20123 -> x\n\
+            \x20456 -> y\n\
+            \x20half_add(x, y) -> (s0, c0)\
+            ",
+            HALF_ADD
+        );
+        let circuit = Circuit::from_program(true, &program);
+        assert_eq!(circuit.statements.len(), 6);
+        assert!(circuit
+            .statements
+            .iter()
+            .any(|stmt| stmt.assignee() == "half_add.0.o"));
+
+        let slots = circuit.run();
+        assert_eq!(wire(&slots, "s0"), 123 ^ 456);
+        assert_eq!(wire(&slots, "c0"), 123 & 456);
+        assert_eq!(wire(&slots, "half_add.0.o"), 123 | 456);
+    }
+
+    #[test]
+    fn instances_get_their_own_wires() {
+        let program = format!(
+            "\
+            \x20half_add(1, 3) -> (s0, c0)\n\
+            \x20half_add(c0, 6) -> (s1, c1)\n\
+            {}",
+            HALF_ADD
+        );
+        let circuit = Circuit::from_program(true, &program);
+        let slots = circuit.run();
+        assert_eq!(wire(&slots, "s0"), 2);
+        assert_eq!(wire(&slots, "c0"), 1);
+        assert_eq!(wire(&slots, "s1"), 7);
+        assert_eq!(wire(&slots, "c1"), 0);
+        assert_eq!(wire(&slots, "half_add.0.o"), 3);
+        assert_eq!(wire(&slots, "half_add.1.o"), 7);
+    }
+
+    #[test]
+    fn nested_instances() {
+        let program = format!(
+            "{}\
+            \x20module full_add(a, b, cin) -> (s, cout) {{\n\
+            \x20    half_add(a, b) -> (s1, c1)\n\
+            \x20    half_add(s1, cin) -> (s, c2)\n\
+            \x20    c1 OR c2 -> cout\n\
+            \x20}}\n\
+            \x205 -> x\n\
+            \x203 -> y\n\
+            \x20full_add(x, y, 1) -> (s, c)\
+            ",
+            HALF_ADD
+        );
+        let circuit = Circuit::from_program(true, &program);
+        let slots = circuit.run();
+        assert_eq!(wire(&slots, "s"), 5 ^ 3 ^ 1);
+        assert_eq!(wire(&slots, "c"), 1);
+        assert_eq!(wire(&slots, "full_add.0.s1"), 5 ^ 3);
+        assert_eq!(wire(&slots, "full_add.0.half_add.1.o"), (5 ^ 3) | 1);
+    }
+
+    fn error(program: &str) -> String {
+        Circuit::parse(true, program).err().expect("an error")
+    }
+
+    #[test]
+    fn arity_mismatch() {
+        let program = format!("{}half_add(x) -> (s0, c0)", HALF_ADD);
+        assert_eq!(
+            error(&program),
+            "Module 'half_add' takes 2 inputs and 2 outputs, but was given 1 and 2"
+        );
+    }
+
+    #[test]
+    fn invalid_instances() {
+        assert_eq!(
+            error(&format!("{}half_add(x y, 1) -> (s, c)", HALF_ADD)),
+            "Instance of module 'half_add' has invalid input 'x y'"
+        );
+        assert_eq!(
+            error(&format!("{}half_add(x, 1) -> (s, 1)", HALF_ADD)),
+            "Instance of module 'half_add' has invalid output '1'"
+        );
+        let program = format!(
+            "{}module add(a, b) -> (s) {{\nhalf_add(a, b) -> (s, c-1)\n}}\nadd(1, 2) -> (x)",
+            HALF_ADD
+        );
+        assert_eq!(
+            error(&program),
+            "Instance of module 'half_add' has invalid output 'c-1'"
+        );
+    }
+
+    #[test]
+    fn recursive_module() {
+        let program = "\
+            \x20module loop(a) -> (b) {\n\
+            \x20    loop(a) -> (b)\n\
+            \x20}\n\
+            \x20loop(1) -> (x)\
+            ";
+        assert_eq!(error(program), "Module 'loop' instantiates itself");
+    }
+
+    #[test]
+    fn unterminated_module() {
+        assert_eq!(
+            error("module half_add(a, b) -> (s, c) {\na AND b -> c"),
+            "Module 'half_add' is missing its closing '}'"
+        );
+    }
+
+    #[test]
+    fn invalid_definitions() {
+        let define = |header: &str, body: &str| error(&format!("{} {{\n{}\n}}", header, body));
+        assert_eq!(
+            define("module inv(a) -> (b, c)", "NOT a -> b"),
+            "Module 'inv' never assigns its output 'c'"
+        );
+        assert_eq!(
+            define("module inv(a) -> (b)", "NOT b -> a"),
+            "Module 'inv' assigns to its input 'a'"
+        );
+        assert_eq!(
+            define("module inv(a) -> (a)", "NOT a -> a"),
+            "Module 'inv' declares 'a' more than once"
+        );
+        assert_eq!(
+            define("module inv(a b) -> (c)", "NOT a -> c"),
+            "Module 'inv' declares invalid wire 'a b'"
+        );
+        assert_eq!(
+            define("module inv(a) -> (b)", "NOT a => b"),
+            "Unable to add 'NOT a => b' in module 'inv'"
+        );
+        assert_eq!(
+            error("module inv(a) -> (b) {\nmodule x() -> () {\n}\n}"),
+            "Cannot define a module inside module 'inv'"
+        );
+        assert_eq!(
+            error("module inv(a) -> (b) {\nNOT a -> b\n}\nmodule inv(a) -> (b) {\nNOT a -> b\n}"),
+            "Module 'inv' is defined more than once"
+        );
+        // outputs of inner instances count as assigned
+        let program = format!(
+            "{}module add(a, b) -> (s) {{\nhalf_add(a, b) -> (s, c)\n}}\nadd(1, 2) -> (x)",
+            HALF_ADD
+        );
+        assert!(Circuit::parse(true, &program).is_ok());
+    }
+}
@@ -0,0 +1,400 @@
+/*
+ * String literals of the languages our services emit.
+ *
+ * Rust and JSON literals are text, so their decoded bytes are always valid UTF-8 and bytes which
+ * are not cannot be encoded. C literals are plain bytes, the in memory length does not include the
+ * terminating NUL.
+ */
+use std::fmt::Write;
+
+use super::literal::{decode_error, hex_digits, DecodeError, DecodeErrorKind, Dialect};
+
+fn push_char(decoded: &mut Vec<u8>, c: char) {
+    decoded.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+// Rust string literals with `\n`, `\r`, `\t`, `\0`, `\'`, `\xNN` up to 0x7f and `\u{N...}`
+pub struct Rust;
+
+impl Rust {
+    // Decodes the braced hex digits of `\u{...}`, which may contain underscores after the first
+    fn decode_unicode(bytes: &[u8], start: usize) -> Result<(char, usize), DecodeError> {
+        let invalid = || decode_error(start, DecodeErrorKind::InvalidCodePoint);
+        if bytes.get(start + 2) != Some(&b'{') {
+            return invalid();
+        }
+        let mut value: u32 = 0;
+        let mut ndigits = 0;
+        let mut i = start + 3;
+        loop {
+            match bytes.get(i) {
+                Some(b'}') if ndigits > 0 => break,
+                Some(b'_') if ndigits > 0 => {}
+                Some(digit) if ndigits < 6 && digit.is_ascii_hexdigit() => {
+                    value = value << 4 | (*digit as char).to_digit(16).unwrap();
+                    ndigits += 1;
+                }
+                _ => return invalid(),
+            }
+            i += 1;
+        }
+        match char::from_u32(value) {
+            Some(c) => Ok((c, i + 1)),
+            None => invalid(),
+        }
+    }
+}
+
+impl Dialect for Rust {
+    fn decode_escape(
+        &self,
+        bytes: &[u8],
+        start: usize,
+        decoded: &mut Vec<u8>,
+    ) -> Result<usize, DecodeError> {
+        let simple = match bytes.get(start + 1) {
+            Some(b'n') => b'\n',
+            Some(b'r') => b'\r',
+            Some(b't') => b'\t',
+            Some(b'0') => b'\0',
+            Some(b'\'') => b'\'',
+            Some(b'x') => match hex_digits(bytes, start + 2, start + 4) {
+                Some(value) if value <= 0x7f => {
+                    decoded.push(value as u8);
+                    return Ok(start + 4);
+                }
+                Some(_) => return decode_error(start, DecodeErrorKind::OutOfRange),
+                None => return decode_error(start, DecodeErrorKind::TruncatedHex),
+            },
+            Some(b'u') => {
+                let (c, end) = Rust::decode_unicode(bytes, start)?;
+                push_char(decoded, c);
+                return Ok(end);
+            }
+            _ => return decode_error(start, DecodeErrorKind::InvalidEscape),
+        };
+        decoded.push(simple);
+        Ok(start + 2)
+    }
+
+    fn named_escape(&self, c: char) -> Option<&'static str> {
+        match c {
+            '\n' => Some("\\n"),
+            '\r' => Some("\\r"),
+            '\t' => Some("\\t"),
+            '\0' => Some("\\0"),
+            _ => None,
+        }
+    }
+
+    fn escape_char(&self, c: char, encoded: &mut String) {
+        if c.is_ascii() {
+            write!(encoded, "\\x{:02x}", c as u32).unwrap();
+        } else {
+            write!(encoded, "\\u{{{:x}}}", c as u32).unwrap();
+        }
+    }
+
+    fn escape_byte(&self, _byte: u8, _encoded: &mut String) -> bool {
+        false
+    }
+}
+
+// JSON strings with `\/`, `\b`, `\f`, `\n`, `\r`, `\t` and `\uXXXX`, where characters beyond the
+// basic multilingual plane are a pair of surrogates. Control characters need to be escaped.
+pub struct Json;
+
+impl Json {
+    // The UTF-16 code unit of the `\uXXXX` at `start`
+    fn code_unit(bytes: &[u8], start: usize) -> Option<u32> {
+        if bytes.get(start..start + 2) != Some(b"\\u") {
+            return None;
+        }
+        hex_digits(bytes, start + 2, start + 6)
+    }
+}
+
+impl Dialect for Json {
+    fn decode_escape(
+        &self,
+        bytes: &[u8],
+        start: usize,
+        decoded: &mut Vec<u8>,
+    ) -> Result<usize, DecodeError> {
+        let simple = match bytes.get(start + 1) {
+            Some(b'/') => b'/',
+            Some(b'b') => 0x08,
+            Some(b'f') => 0x0c,
+            Some(b'n') => b'\n',
+            Some(b'r') => b'\r',
+            Some(b't') => b'\t',
+            Some(b'u') => {
+                let invalid = || decode_error(start, DecodeErrorKind::InvalidCodePoint);
+                let (value, end) = match Json::code_unit(bytes, start) {
+                    Some(high @ 0xd800..=0xdbff) => match Json::code_unit(bytes, start + 6) {
+                        Some(low @ 0xdc00..=0xdfff) => (
+                            0x10000 + ((high - 0xd800) << 10 | (low - 0xdc00)),
+                            start + 12,
+                        ),
+                        _ => return invalid(),
+                    },
+                    Some(value) => (value, start + 6),
+                    None => return invalid(),
+                };
+                // a lone low surrogate is no character
+                return match char::from_u32(value) {
+                    Some(c) => {
+                        push_char(decoded, c);
+                        Ok(end)
+                    }
+                    None => invalid(),
+                };
+            }
+            _ => return decode_error(start, DecodeErrorKind::InvalidEscape),
+        };
+        decoded.push(simple);
+        Ok(start + 2)
+    }
+
+    fn allows_raw(&self, byte: u8) -> bool {
+        byte >= 0x20
+    }
+
+    fn named_escape(&self, c: char) -> Option<&'static str> {
+        match c {
+            '\u{8}' => Some("\\b"),
+            '\u{c}' => Some("\\f"),
+            '\n' => Some("\\n"),
+            '\r' => Some("\\r"),
+            '\t' => Some("\\t"),
+            _ => None,
+        }
+    }
+
+    fn escape_char(&self, c: char, encoded: &mut String) {
+        for unit in c.encode_utf16(&mut [0; 2]) {
+            write!(encoded, "\\u{:04x}", unit).unwrap();
+        }
+    }
+
+    fn escape_byte(&self, _byte: u8, _encoded: &mut String) -> bool {
+        false
+    }
+}
+
+// C string literals with `\a`, `\b`, `\f`, `\n`, `\r`, `\t`, `\v`, `\'`, `\?`, octal escapes of up
+// to three digits and hex escapes of any number of digits
+pub struct C;
+
+impl C {
+    // Decodes up to `max_digits` digits from `from` on, returning the offset following them
+    fn decode_number(
+        bytes: &[u8],
+        start: usize,
+        from: usize,
+        radix: u32,
+        max_digits: usize,
+        decoded: &mut Vec<u8>,
+    ) -> Result<usize, DecodeError> {
+        let mut value = 0;
+        let mut end = from;
+        while let Some(digit) = bytes
+            .get(end)
+            .filter(|_| end - from < max_digits)
+            .and_then(|digit| (*digit as char).to_digit(radix))
+        {
+            value = value * radix + digit;
+            if value > 0xff {
+                return decode_error(start, DecodeErrorKind::OutOfRange);
+            }
+            end += 1;
+        }
+        if end > from {
+            decoded.push(value as u8);
+        }
+        Ok(end)
+    }
+}
+
+impl Dialect for C {
+    fn decode_escape(
+        &self,
+        bytes: &[u8],
+        start: usize,
+        decoded: &mut Vec<u8>,
+    ) -> Result<usize, DecodeError> {
+        let simple = match bytes.get(start + 1) {
+            Some(b'a') => 0x07,
+            Some(b'b') => 0x08,
+            Some(b'f') => 0x0c,
+            Some(b'n') => b'\n',
+            Some(b'r') => b'\r',
+            Some(b't') => b'\t',
+            Some(b'v') => 0x0b,
+            Some(b'\'') => b'\'',
+            Some(b'?') => b'?',
+            Some(b'0'..=b'7') => return C::decode_number(bytes, start, start + 1, 8, 3, decoded),
+            Some(b'x') => {
+                let end = C::decode_number(bytes, start, start + 2, 16, usize::MAX, decoded)?;
+                if end == start + 2 {
+                    return decode_error(start, DecodeErrorKind::InvalidEscape);
+                }
+                return Ok(end);
+            }
+            _ => return decode_error(start, DecodeErrorKind::InvalidEscape),
+        };
+        decoded.push(simple);
+        Ok(start + 2)
+    }
+
+    fn named_escape(&self, c: char) -> Option<&'static str> {
+        match c {
+            '\u{7}' => Some("\\a"),
+            '\u{8}' => Some("\\b"),
+            '\u{c}' => Some("\\f"),
+            '\n' => Some("\\n"),
+            '\r' => Some("\\r"),
+            '\t' => Some("\\t"),
+            '\u{b}' => Some("\\v"),
+            _ => None,
+        }
+    }
+
+    // Always three octal digits, so a following digit is not taken as part of the escape
+    fn escape_char(&self, c: char, encoded: &mut String) {
+        for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+            self.escape_byte(byte, encoded);
+        }
+    }
+
+    fn escape_byte(&self, byte: u8, encoded: &mut String) -> bool {
+        write!(encoded, "\\{:03o}", byte).unwrap();
+        true
+    }
+}
+
+pub fn dialect(name: &str) -> Option<&'static dyn Dialect> {
+    match name {
+        "puzzle" => Some(&super::literal::Puzzle),
+        "rust" => Some(&Rust),
+        "json" => Some(&Json),
+        "c" => Some(&C),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::literal::{EncodeError, EncodeOptions, HexEscape, Unit};
+
+    fn error(offset: usize, kind: DecodeErrorKind) -> Result<Vec<u8>, DecodeError> {
+        decode_error(offset, kind)
+    }
+
+    fn encode(dialect: &dyn Dialect, text: &str, hex: HexEscape) -> String {
+        dialect
+            .encode(text.as_bytes(), &EncodeOptions { hex })
+            .unwrap()
+    }
+
+    #[test]
+    fn rust_literals() {
+        use DecodeErrorKind::*;
+        assert_eq!(
+            Rust.decode(r#""a\n\t\0\'\x41\u{3c0}\u{1F6_00}""#),
+            Ok("a\n\t\0'Aπ😀".as_bytes().to_vec())
+        );
+        assert_eq!(Rust.decode(r#""a\x80""#), error(2, OutOfRange));
+        assert_eq!(Rust.decode(r#""\u{d800}""#), error(1, InvalidCodePoint));
+        assert_eq!(Rust.decode(r#""\u{}""#), error(1, InvalidCodePoint));
+        assert_eq!(Rust.decode(r#""\u{1000000}""#), error(1, InvalidCodePoint));
+        assert_eq!(Rust.decode(r#""\u3c0""#), error(1, InvalidCodePoint));
+        assert_eq!(Rust.decode(r#""\a""#), error(1, InvalidEscape));
+
+        let text = "π\"\n\u{1}";
+        assert_eq!(
+            encode(&Rust, text, HexEscape::Unprintable),
+            r#""\u{3c0}\"\n\x01""#
+        );
+        assert_eq!(
+            encode(&Rust, text, HexEscape::InvalidUtf8),
+            "\"π\\\"\\n\u{1}\""
+        );
+        assert_eq!(
+            encode(&Rust, text, HexEscape::All),
+            r#""\u{3c0}\x22\x0a\x01""#
+        );
+        assert_eq!(
+            Rust.encode(b"ab\xff", &EncodeOptions::default()),
+            Err(EncodeError { offset: 2 })
+        );
+    }
+
+    #[test]
+    fn json_strings() {
+        use DecodeErrorKind::*;
+        assert_eq!(
+            Json.decode(r#""\u00e9\/\b\ud83d\ude00""#),
+            Ok("é/\u{8}😀".as_bytes().to_vec())
+        );
+        assert_eq!(Json.decode(r#""a\ud83d""#), error(2, InvalidCodePoint));
+        assert_eq!(Json.decode(r#""\ud83dx""#), error(1, InvalidCodePoint));
+        assert_eq!(Json.decode(r#""\ude00""#), error(1, InvalidCodePoint));
+        assert_eq!(Json.decode(r#""\u12""#), error(1, InvalidCodePoint));
+        assert_eq!(Json.decode(r#""\x41""#), error(1, InvalidEscape));
+        assert_eq!(Json.decode("\"a\tb\""), error(2, UnescapedControl));
+
+        let text = "😀\t\u{1}/";
+        assert_eq!(
+            encode(&Json, text, HexEscape::Unprintable),
+            r#""\ud83d\ude00\t\u0001/""#
+        );
+        assert_eq!(
+            encode(&Json, text, HexEscape::InvalidUtf8),
+            r#""😀\t\u0001/""#
+        );
+        assert!(Json.encode(b"\x80", &EncodeOptions::default()).is_err());
+    }
+
+    #[test]
+    fn c_literals() {
+        use DecodeErrorKind::*;
+        assert_eq!(
+            C.decode(r#""\101\0\x41\x000041\?\a\1234""#),
+            Ok(b"A\0AA?\x07S4".to_vec())
+        );
+        assert_eq!(C.decode(r#""\xff\377""#), Ok(vec![0xff, 0xff]));
+        assert_eq!(C.decode(r#""a\400""#), error(2, OutOfRange));
+        assert_eq!(C.decode(r#""\x100""#), error(1, OutOfRange));
+        assert_eq!(C.decode(r#""\xg""#), error(1, InvalidEscape));
+        assert_eq!(C.decode(r#""\e""#), error(1, InvalidEscape));
+
+        assert_eq!(
+            C.encode(b"\xff7\n\x0b\"", &EncodeOptions::default()),
+            Ok(r#""\3777\n\v\"""#.to_string())
+        );
+        assert_eq!(encode(&C, "π", HexEscape::Unprintable), r#""\317\200""#);
+        assert_eq!(encode(&C, "π", HexEscape::InvalidUtf8), r#""π""#);
+    }
+
+    #[test]
+    fn lengths() {
+        assert_eq!(Rust.lengths(r#""\u{3c0}""#, Unit::Bytes), Ok((9, 2)));
+        assert_eq!(Rust.lengths(r#""\u{3c0}""#, Unit::Chars), Ok((9, 1)));
+        assert_eq!(Json.lengths(r#""\ud83d\ude00""#, Unit::Utf16), Ok((14, 2)));
+        // without the terminating NUL
+        assert_eq!(C.lengths(r#""\0""#, Unit::Bytes), Ok((4, 1)));
+        assert_eq!(
+            Rust.encoded_lengths(r#""\u{3c0}""#, Unit::Bytes),
+            Ok((9, 14))
+        );
+        assert_eq!(Json.encoded_lengths(r#""a""#, Unit::Bytes), Ok((3, 7)));
+        assert_eq!(C.encoded_lengths(r#""π""#, Unit::Chars), Ok((3, 14)));
+        assert!(dialect("python").is_none());
+        assert_eq!(
+            dialect("puzzle").unwrap().lengths(r#""\x27""#, Unit::Bytes),
+            Ok((6, 1))
+        );
+    }
+}
@@ -0,0 +1,482 @@
+/*
+ * Decoding and encoding of string literals.
+ *
+ * All dialects enclose literals in double quotes and escape them as well as backslashes with a
+ * backslash. A `Dialect` defines the remaining escapes, which bytes may appear unescaped and how
+ * characters are escaped by value. The literals of the puzzle only know `\xNN` on top, where `NN`
+ * are two hexadecimal digits. Since escapes can produce any byte, decoded literals are sequences
+ * of bytes rather than strings.
+ *
+ * The puzzle measures lengths in bytes, which is what a literal and its value take up in memory.
+ * For text the number of characters, i.e. code points, or of UTF-16 code units may be what
+ * matters instead. None of them is the number of graphemes a reader would count, `"é"` is two
+ * characters if the accent is a combining one.
+ */
+use std::fmt::{self, Write};
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeErrorKind {
+    // an empty line rather than the empty literal `""`
+    Empty,
+    MissingOpeningQuote,
+    MissingClosingQuote,
+    // characters after the closing quote, i.e. an unescaped quote inside the literal
+    TrailingCharacters,
+    InvalidEscape,
+    TruncatedHex,
+    // a malformed unicode escape or one of a surrogate or a value beyond U+10FFFF
+    InvalidCodePoint,
+    // an escape whose value does not fit into the type of the literal
+    OutOfRange,
+    UnescapedControl,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DecodeError {
+    // the byte offset within the literal at which the problem was found
+    pub offset: usize,
+    pub kind: DecodeErrorKind,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.kind {
+            DecodeErrorKind::Empty => return write!(f, "empty literal"),
+            DecodeErrorKind::MissingOpeningQuote => "missing opening quote",
+            DecodeErrorKind::MissingClosingQuote => "missing closing quote",
+            DecodeErrorKind::TrailingCharacters => "characters after the closing quote",
+            DecodeErrorKind::InvalidEscape => "invalid escape",
+            DecodeErrorKind::TruncatedHex => "\\x not followed by two hex digits",
+            DecodeErrorKind::InvalidCodePoint => "invalid unicode escape",
+            DecodeErrorKind::OutOfRange => "escape out of range",
+            DecodeErrorKind::UnescapedControl => "unescaped control character",
+        };
+        write!(f, "{} at byte {}", message, self.offset)
+    }
+}
+
+pub fn decode_error<T>(offset: usize, kind: DecodeErrorKind) -> Result<T, DecodeError> {
+    Err(DecodeError { offset, kind })
+}
+
+#[derive(Debug, PartialEq)]
+pub struct EncodeError {
+    // the byte offset within the input of a byte the dialect cannot represent
+    pub offset: usize,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid UTF-8 at byte {} cannot be encoded", self.offset)
+    }
+}
+
+fn hex_value(digit: u8) -> Option<u32> {
+    (digit as char).to_digit(16)
+}
+
+// The value of the hex digits within bytes[from..to], if there are that many and all are digits
+pub fn hex_digits(bytes: &[u8], from: usize, to: usize) -> Option<u32> {
+    bytes.get(from..to)?.iter().try_fold(0, |value, digit| {
+        hex_value(*digit).map(|digit| value << 4 | digit)
+    })
+}
+
+// Which characters are escaped by value, quotes and backslashes are escaped with a backslash and
+// control characters with their short escape otherwise
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HexEscape {
+    // everything but printable ASCII, so the literal is plain ASCII
+    Unprintable,
+    // only bytes which are not part of valid UTF-8 and control characters, so text stays readable
+    InvalidUtf8,
+    All,
+}
+
+impl HexEscape {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unprintable" => Some(HexEscape::Unprintable),
+            "utf8" => Some(HexEscape::InvalidUtf8),
+            "all" => Some(HexEscape::All),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    pub hex: HexEscape,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            hex: HexEscape::Unprintable,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Unit {
+    Bytes,
+    // code points
+    Chars,
+    Utf16,
+}
+
+impl Unit {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bytes" => Some(Unit::Bytes),
+            "chars" => Some(Unit::Chars),
+            "utf16" => Some(Unit::Utf16),
+            _ => None,
+        }
+    }
+
+    // The length of the bytes in this unit, where every sequence of bytes which is not valid
+    // UTF-8 counts as the U+FFFD it would be replaced with
+    pub fn len(self, bytes: &[u8]) -> usize {
+        match self {
+            Unit::Bytes => bytes.len(),
+            Unit::Chars => String::from_utf8_lossy(bytes).chars().count(),
+            Unit::Utf16 => String::from_utf8_lossy(bytes).encode_utf16().count(),
+        }
+    }
+}
+
+pub trait Dialect {
+    // Decodes the escape whose backslash is at `start`, returning the offset following it
+    fn decode_escape(
+        &self,
+        bytes: &[u8],
+        start: usize,
+        decoded: &mut Vec<u8>,
+    ) -> Result<usize, DecodeError>;
+
+    // Whether the byte may appear unescaped, quotes and backslashes aside
+    fn allows_raw(&self, _byte: u8) -> bool {
+        true
+    }
+
+    // The short escape of a character, like `\n`
+    fn named_escape(&self, _c: char) -> Option<&'static str> {
+        None
+    }
+
+    // Escapes a character by value
+    fn escape_char(&self, c: char, encoded: &mut String);
+
+    // Escapes a byte which is not part of valid UTF-8, false if the dialect has no way to do so
+    fn escape_byte(&self, byte: u8, encoded: &mut String) -> bool;
+
+    fn decode(&self, literal: &str) -> Result<Vec<u8>, DecodeError> {
+        let bytes = literal.as_bytes();
+        if bytes.is_empty() {
+            return decode_error(0, DecodeErrorKind::Empty);
+        }
+        if bytes[0] != b'"' {
+            return decode_error(0, DecodeErrorKind::MissingOpeningQuote);
+        }
+
+        let mut decoded = vec![];
+        let mut i = 1;
+        loop {
+            match bytes.get(i) {
+                None => return decode_error(i, DecodeErrorKind::MissingClosingQuote),
+                Some(b'"') if i + 1 == bytes.len() => return Ok(decoded),
+                Some(b'"') => return decode_error(i + 1, DecodeErrorKind::TrailingCharacters),
+                Some(b'\\') => match bytes.get(i + 1) {
+                    Some(b'\\') | Some(b'"') => {
+                        decoded.push(bytes[i + 1]);
+                        i += 2;
+                    }
+                    _ => i = self.decode_escape(bytes, i, &mut decoded)?,
+                },
+                Some(byte) if !self.allows_raw(*byte) => {
+                    return decode_error(i, DecodeErrorKind::UnescapedControl)
+                }
+                Some(byte) => {
+                    decoded.push(*byte);
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    fn encode(&self, bytes: &[u8], options: &EncodeOptions) -> Result<String, EncodeError> {
+        let mut encoded = String::from("\"");
+        let mut offset = 0;
+        for chunk in bytes.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                let raw = match options.hex {
+                    HexEscape::Unprintable => (' '..='~').contains(&c),
+                    HexEscape::InvalidUtf8 => {
+                        !c.is_control()
+                            || c.is_ascii()
+                                && self.allows_raw(c as u8)
+                                && self.named_escape(c).is_none()
+                    }
+                    HexEscape::All => false,
+                };
+                if raw {
+                    if c == '"' || c == '\\' {
+                        encoded.push('\\');
+                    }
+                    encoded.push(c);
+                } else {
+                    match self.named_escape(c) {
+                        Some(named) if options.hex != HexEscape::All => encoded.push_str(named),
+                        _ => self.escape_char(c, &mut encoded),
+                    }
+                }
+            }
+            offset += chunk.valid().len();
+            for byte in chunk.invalid() {
+                if !self.escape_byte(*byte, &mut encoded) {
+                    return Err(EncodeError { offset });
+                }
+                offset += 1;
+            }
+        }
+        encoded.push('"');
+        Ok(encoded)
+    }
+
+    // The lengths of the literal and of its decoded value
+    fn lengths(&self, literal: &str, unit: Unit) -> Result<(usize, usize), DecodeError> {
+        let decoded = self.decode(literal)?;
+        Ok((unit.len(literal.as_bytes()), unit.len(&decoded)))
+    }
+
+    // The lengths of the literal and of the literal encoded as a literal of this dialect
+    fn encoded_lengths(&self, literal: &str, unit: Unit) -> Result<(usize, usize), DecodeError> {
+        self.decode(literal)?;
+        let encoded = self
+            .encode(literal.as_bytes(), &EncodeOptions::default())
+            .expect("a str is valid UTF-8");
+        Ok((unit.len(literal.as_bytes()), unit.len(encoded.as_bytes())))
+    }
+}
+
+fn push_hex(encoded: &mut String, byte: u8) {
+    write!(encoded, "\\x{:02x}", byte).unwrap();
+}
+
+// The literals of the puzzle
+pub struct Puzzle;
+
+impl Dialect for Puzzle {
+    fn decode_escape(
+        &self,
+        bytes: &[u8],
+        start: usize,
+        decoded: &mut Vec<u8>,
+    ) -> Result<usize, DecodeError> {
+        match bytes.get(start + 1) {
+            Some(b'x') => match hex_digits(bytes, start + 2, start + 4) {
+                Some(value) => {
+                    decoded.push(value as u8);
+                    Ok(start + 4)
+                }
+                None => decode_error(start, DecodeErrorKind::TruncatedHex),
+            },
+            _ => decode_error(start, DecodeErrorKind::InvalidEscape),
+        }
+    }
+
+    fn escape_char(&self, c: char, encoded: &mut String) {
+        for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+            push_hex(encoded, byte);
+        }
+    }
+
+    fn escape_byte(&self, byte: u8, encoded: &mut String) -> bool {
+        push_hex(encoded, byte);
+        true
+    }
+}
+
+pub fn decode_literal(literal: &str) -> Result<Vec<u8>, DecodeError> {
+    Puzzle.decode(literal)
+}
+
+pub fn encode_literal(bytes: &[u8]) -> String {
+    Puzzle
+        .encode(bytes, &EncodeOptions::default())
+        .expect("every byte can be hex escaped")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dialects::{Json, Rust, C};
+
+    fn error(offset: usize, kind: DecodeErrorKind) -> Result<Vec<u8>, DecodeError> {
+        decode_error(offset, kind)
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(decode_literal(r#""""#), Ok(vec![]));
+        assert_eq!(decode_literal(r#""abc""#), Ok(b"abc".to_vec()));
+        assert_eq!(decode_literal(r#""aaa\"aaa""#), Ok(b"aaa\"aaa".to_vec()));
+        assert_eq!(decode_literal(r#""a\\b""#), Ok(b"a\\b".to_vec()));
+        assert_eq!(decode_literal(r#""\x27""#), Ok(b"'".to_vec()));
+        assert_eq!(
+            decode_literal(r#""\xa8br\x8B\x00""#),
+            Ok(vec![0xa8, b'b', b'r', 0x8b, 0x00])
+        );
+    }
+
+    #[test]
+    fn reports_offsets() {
+        use DecodeErrorKind::*;
+        assert_eq!(decode_literal(""), error(0, Empty));
+        assert_eq!(decode_literal("abc\""), error(0, MissingOpeningQuote));
+        assert_eq!(decode_literal("\""), error(1, MissingClosingQuote));
+        assert_eq!(decode_literal(r#""abc\""#), error(6, MissingClosingQuote));
+        assert_eq!(decode_literal(r#""ab"c""#), error(4, TrailingCharacters));
+        assert_eq!(decode_literal(r#""ab\qc""#), error(3, InvalidEscape));
+        assert_eq!(decode_literal(r#""ab\"#), error(3, InvalidEscape));
+        assert_eq!(decode_literal(r#""a\x2""#), error(2, TruncatedHex));
+        assert_eq!(decode_literal(r#""a\xg0""#), error(2, TruncatedHex));
+        assert_eq!(decode_literal(r#""a\x"#), error(2, TruncatedHex));
+        assert_eq!(decode_literal("").unwrap_err().to_string(), "empty literal");
+        assert_eq!(
+            decode_literal(r#""a\x2""#).unwrap_err().to_string(),
+            "\\x not followed by two hex digits at byte 2"
+        );
+    }
+
+    #[test]
+    fn encodes_escapes() {
+        assert_eq!(encode_literal(b""), r#""""#);
+        assert_eq!(encode_literal(b"abc"), r#""abc""#);
+        assert_eq!(encode_literal(b"aaa\"aaa"), r#""aaa\"aaa""#);
+        assert_eq!(encode_literal(b"\\x27"), r#""\\x27""#);
+        assert_eq!(encode_literal(&[0xa8, b'b', 0x0a]), r#""\xa8b\x0a""#);
+
+        let options = |hex| EncodeOptions { hex };
+        let text = "π \"\n".as_bytes();
+        assert_eq!(
+            Puzzle
+                .encode(text, &options(HexEscape::Unprintable))
+                .unwrap(),
+            r#""\xcf\x80 \"\x0a""#
+        );
+        assert_eq!(
+            Puzzle
+                .encode(text, &options(HexEscape::InvalidUtf8))
+                .unwrap(),
+            "\"π \\\"\n\""
+        );
+        assert_eq!(
+            Puzzle
+                .encode(&[0xcf, b'a', 0xcf], &options(HexEscape::InvalidUtf8))
+                .unwrap(),
+            r#""\xcfa\xcf""#
+        );
+        assert_eq!(
+            Puzzle.encode(b"a\"", &options(HexEscape::All)).unwrap(),
+            r#""\x61\x22""#
+        );
+    }
+
+    #[test]
+    fn units() {
+        let text = "é😀".as_bytes();
+        assert_eq!(Unit::Bytes.len(text), 6);
+        assert_eq!(Unit::Chars.len(text), 2);
+        assert_eq!(Unit::Utf16.len(text), 3);
+        // a combining accent is a character of its own
+        assert_eq!(Unit::Chars.len("e\u{301}".as_bytes()), 2);
+        assert_eq!(Unit::Chars.len(b"a\xff\xfe"), 3);
+        assert_eq!(Unit::Utf16.len(b"\xf0\x9f\x98"), 1);
+    }
+
+    #[test]
+    fn multibyte_lengths() {
+        let literal = r#""é\x41😀""#;
+        assert_eq!(decode_literal(literal), Ok("éA😀".as_bytes().to_vec()));
+        assert_eq!(Puzzle.lengths(literal, Unit::Bytes), Ok((12, 7)));
+        assert_eq!(Puzzle.lengths(literal, Unit::Chars), Ok((8, 3)));
+        assert_eq!(Puzzle.lengths(literal, Unit::Utf16), Ok((9, 4)));
+        // the encoded literal is plain ASCII
+        assert_eq!(Puzzle.encoded_lengths(literal, Unit::Bytes), Ok((12, 35)));
+        assert_eq!(Puzzle.encoded_lengths(literal, Unit::Chars), Ok((8, 35)));
+        // bytes which are not valid UTF-8 still count in memory
+        assert_eq!(Puzzle.lengths(r#""\xff\xfe""#, Unit::Chars), Ok((10, 2)));
+        assert_eq!(
+            Puzzle.encoded_lengths("\"é", Unit::Bytes),
+            decode_error(3, DecodeErrorKind::MissingClosingQuote)
+        );
+    }
+
+    // xorshift, which is good enough to come up with arbitrary bytes
+    fn random_bytes(state: &mut u64) -> Vec<u8> {
+        let mut next = || {
+            *state ^= *state << 13;
+            *state ^= *state >> 7;
+            *state ^= *state << 17;
+            *state
+        };
+        let len = next() % 40;
+        // favor the bytes that need escaping and the ones that could be taken as part of an escape
+        let interesting: [&[u8]; 12] = [
+            b"\"",
+            b"\\",
+            b"x",
+            b"7",
+            b"f",
+            b"\n",
+            b"\x00",
+            b"\x7f",
+            b"\xff",
+            b"\xcf\x80",
+            b"\xf0\x9f\x98\x80",
+            b"\xed\xa0\x80",
+        ];
+        let mut bytes = vec![];
+        for _ in 0..len {
+            match next() % 3 {
+                0 => bytes.extend_from_slice(interesting[next() as usize % interesting.len()]),
+                _ => bytes.push(next() as u8),
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn decode_undoes_encode() {
+        let dialects: [&dyn Dialect; 4] = [&Puzzle, &Rust, &Json, &C];
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..2000 {
+            let bytes = random_bytes(&mut state);
+            let text = String::from_utf8_lossy(&bytes);
+            for dialect in dialects.iter() {
+                for hex in [
+                    HexEscape::Unprintable,
+                    HexEscape::InvalidUtf8,
+                    HexEscape::All,
+                ]
+                .iter()
+                {
+                    let options = EncodeOptions { hex: *hex };
+                    // text dialects cannot encode invalid UTF-8
+                    let (bytes, encoded) = match dialect.encode(&bytes, &options) {
+                        Ok(encoded) => (bytes.clone(), encoded),
+                        Err(_) => (
+                            text.as_bytes().to_vec(),
+                            dialect.encode(text.as_bytes(), &options).unwrap(),
+                        ),
+                    };
+                    assert_eq!(dialect.decode(&encoded), Ok(bytes), "{}", encoded);
+                }
+            }
+            // multi-byte characters need valid UTF-8, which is mostly not the case here
+            assert!(encode_literal(&bytes).is_ascii());
+        }
+    }
+}
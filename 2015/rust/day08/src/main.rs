@@ -1,98 +1,157 @@
+use std::{env, fs, process};
+
+mod dialects;
+mod literal;
+
+use literal::{
+    decode_literal, encode_literal, DecodeError, Dialect, EncodeOptions, HexEscape, Unit,
+};
+
+fn arg(name: &str) -> Option<String> {
+    env::args().skip_while(|x| x != name).nth(1)
+}
+
+fn dialect_arg(name: &str) -> Option<&'static dyn Dialect> {
+    arg(name).map(|dialect| {
+        dialects::dialect(&dialect).unwrap_or_else(|| {
+            eprintln!("Unknown dialect '{}', use puzzle, rust, json or c", dialect);
+            process::exit(1);
+        })
+    })
+}
+
+// Prints every literal of the list re-encoded with the given choice of hex escapes
+fn reencode(lines: &[&str], from: &dyn Dialect, to: &dyn Dialect, hex: &str) -> Result<(), String> {
+    let hex = HexEscape::from_name(hex).ok_or_else(|| {
+        format!(
+            "Unknown hex escapes '{}', use unprintable, utf8 or all",
+            hex
+        )
+    })?;
+    for (idx, line) in lines.iter().enumerate() {
+        let error = |err: &dyn std::fmt::Display| format!("line {}: {}", idx + 1, err);
+        let bytes = from.decode(line).map_err(|err| error(&err))?;
+        let encoded = to
+            .encode(&bytes, &EncodeOptions { hex })
+            .map_err(|err| error(&err))?;
+        println!("{}", encoded);
+    }
+    Ok(())
+}
+
+// Sums up the lengths of the literals in code, in memory and encoded as literals again
+fn process_list(
+    list: &[&str],
+    dialect: &dyn Dialect,
+    unit: Unit,
+) -> Result<(usize, usize, usize), String> {
+    list.iter().enumerate().try_fold(
+        (0, 0, 0),
+        |(total_in_code, total_in_memory, total_encoded), (idx, line)| {
+            let error = |err| format!("line {}: {}", idx + 1, err);
+            let (in_code, in_memory) = dialect.lengths(line, unit).map_err(error)?;
+            let (_, encoded) = dialect.encoded_lengths(line, unit).map_err(error)?;
+            Ok((
+                total_in_code + in_code,
+                total_in_memory + in_memory,
+                total_encoded + encoded,
+            ))
+        },
+    )
+}
+
 fn main() {
-    let input = include_str!("./input.txt");
+    let input = match arg("--input") {
+        Some(path) => fs::read_to_string(&path).unwrap_or_else(|err| {
+            eprintln!("Unable to read '{}': {}", path, err);
+            process::exit(1);
+        }),
+        None => include_str!("./input.txt").to_string(),
+    };
     let lines: Vec<&str> = input.lines().collect();
-    let (in_code, in_memory) = process_list_part1(&lines);
+    let dialect = dialect_arg("--dialect");
+    if let Some(hex) = arg("--encode") {
+        let from = dialect.unwrap_or(&literal::Puzzle);
+        let to = dialect_arg("--to").unwrap_or(from);
+        if let Err(err) = reencode(&lines, from, to, &hex) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    if let Some(dialect) = dialect {
+        let unit = arg("--units").map_or(Unit::Bytes, |unit| {
+            Unit::from_name(&unit).unwrap_or_else(|| {
+                eprintln!("Unknown units '{}', use bytes, chars or utf16", unit);
+                process::exit(1);
+            })
+        });
+        match process_list(&lines, dialect, unit) {
+            Ok((in_code, in_memory, encoded)) => {
+                println!("in code: {}", in_code);
+                println!("in memory: {}", in_memory);
+                println!("encoded: {}", encoded);
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let (in_code, in_memory) = match process_list_part1(&lines) {
+        Ok(lengths) => lengths,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
     let diff = in_code - in_memory;
     println!("part1: {}", diff);
 
-    let (in_code, encoded) = process_list_part2(&lines);
+    let (in_code, encoded) = match process_list_part2(&lines) {
+        Ok(lengths) => lengths,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
     let diff = encoded - in_code;
     println!("part2: {}", diff);
 }
 
-fn process_list_part1(list: &Vec<&str>) -> (usize, usize) {
-    list.into_iter()
-        .fold((0, 0), |(total_in_code, total_in_memory), line| {
-            let (in_code, in_memory) = string_lengths_part1(line);
-            (total_in_code + in_code, total_in_memory + in_memory)
+fn process_list_part1(list: &[&str]) -> Result<(usize, usize), String> {
+    list.iter()
+        .enumerate()
+        .try_fold((0, 0), |(total_in_code, total_in_memory), (idx, line)| {
+            let (in_code, in_memory) =
+                string_lengths_part1(line).map_err(|err| format!("line {}: {}", idx + 1, err))?;
+            Ok((total_in_code + in_code, total_in_memory + in_memory))
         })
 }
 
-fn string_lengths_part1(line: &str) -> (usize, usize) {
-    let chars: Vec<char> = line.chars().collect();
-    let in_code = chars.len();
-    let mut in_memory = 0;
-    let mut skip = 0;
-    let upper = in_code - 1;
-
-    for i in 1..upper {
-        if skip > 0 {
-            skip -= 1;
-            continue;
-        }
-        let c = chars[i];
-        if let '\\' = c {
-            let nc = chars[i + 1];
-            match nc {
-                '\\' | '"' => {
-                    in_memory += 1;
-                    skip = 1;
-                }
-                'x' => skip = 2,
-                _ => panic!(
-                    "Saw escape character followed by unescapable char when processing '{}'",
-                    line
-                ),
-            }
-        } else {
-            in_memory += 1;
-        }
-    }
-    (in_code, in_memory)
+// Both lengths are in bytes
+fn string_lengths_part1(line: &str) -> Result<(usize, usize), DecodeError> {
+    let in_memory = decode_literal(line)?.len();
+    Ok((line.len(), in_memory))
 }
 
-fn process_list_part2(list: &Vec<&str>) -> (usize, usize) {
-    list.into_iter()
-        .fold((0, 0), |(total_in_code, total_encoded), line| {
-            let (in_code, encoded) = string_lengths_part2(line);
-            (total_in_code + in_code, total_encoded + encoded)
+fn process_list_part2(list: &[&str]) -> Result<(usize, usize), String> {
+    list.iter()
+        .enumerate()
+        .try_fold((0, 0), |(total_in_code, total_encoded), (idx, line)| {
+            let (in_code, encoded) =
+                string_lengths_part2(line).map_err(|err| format!("line {}: {}", idx + 1, err))?;
+            Ok((total_in_code + in_code, total_encoded + encoded))
         })
 }
 
-fn string_lengths_part2(line: &str) -> (usize, usize) {
-    let chars: Vec<char> = line.chars().collect();
-    let in_code = chars.len();
-    let mut encoded = 6;
-    let mut skip = 0;
-    let upper = in_code - 1;
-
-    for i in 1..upper {
-        if skip > 0 {
-            skip -= 1;
-            continue;
-        }
-        let c = chars[i];
-        if let '\\' = c {
-            let nc = chars[i + 1];
-            match nc {
-                '\\' | '"' => {
-                    encoded += 4;
-                    skip = 1;
-                }
-                'x' => {
-                    encoded += 4;
-                    skip = 2;
-                }
-                _ => panic!(
-                    "Saw escape character followed by unescapable char when processing '{}'",
-                    line
-                ),
-            }
-        } else {
-            encoded += 1;
-        }
-    }
-    (in_code, encoded)
+// Both lengths are in bytes, non-ASCII bytes take up four as they are hex escaped
+fn string_lengths_part2(line: &str) -> Result<(usize, usize), DecodeError> {
+    // only literals are encoded
+    decode_literal(line)?;
+    Ok((line.len(), encode_literal(line.as_bytes()).len()))
 }
 
 #[cfg(test)]
@@ -101,25 +160,25 @@ mod test_part1 {
 
     #[test]
     fn empty_string() {
-        let (in_code, in_memory) = string_lengths_part1("\"\"");
+        let (in_code, in_memory) = string_lengths_part1("\"\"").unwrap();
         assert_eq!((in_code, in_memory), (2, 0));
     }
 
     #[test]
     fn abc() {
-        let (in_code, in_memory) = string_lengths_part1("\"abc\"");
+        let (in_code, in_memory) = string_lengths_part1("\"abc\"").unwrap();
         assert_eq!((in_code, in_memory), (5, 3));
     }
 
     #[test]
     fn with_escaped_quote() {
-        let (in_code, in_memory) = string_lengths_part1("\"aaa\\\"aaa\"");
+        let (in_code, in_memory) = string_lengths_part1("\"aaa\\\"aaa\"").unwrap();
         assert_eq!((in_code, in_memory), (10, 7));
     }
 
     #[test]
     fn escaped_apostrophe() {
-        let (in_code, in_memory) = string_lengths_part1("\"\\x27\"");
+        let (in_code, in_memory) = string_lengths_part1("\"\\x27\"").unwrap();
         assert_eq!((in_code, in_memory), (6, 1));
     }
 
@@ -127,9 +186,37 @@ mod test_part1 {
     fn samples() {
         let input = include_str!("./sample.txt");
         let lines: Vec<&str> = input.lines().collect();
-        let (in_code, in_memory) = process_list_part1(&lines);
+        let (in_code, in_memory) = process_list_part1(&lines).unwrap();
         assert_eq!((in_code, in_memory), (23, 11));
     }
+
+    #[test]
+    fn invalid_line() {
+        let lines = vec!["\"abc\"", "\"a\\x4\""];
+        assert_eq!(
+            process_list_part1(&lines),
+            Err("line 2: \\x not followed by two hex digits at byte 2".to_string())
+        );
+    }
+
+    #[test]
+    fn empty_and_unterminated_lines() {
+        assert_eq!(
+            process_list_part1(&["\"abc\"", ""]),
+            Err("line 2: empty literal".to_string())
+        );
+        assert_eq!(
+            process_list_part1(&["\"abc"]),
+            Err("line 1: missing closing quote at byte 4".to_string())
+        );
+    }
+
+    #[test]
+    fn multibyte() {
+        // lengths are in bytes, not chars
+        let (in_code, in_memory) = string_lengths_part1("\"é😀\\x41\"").unwrap();
+        assert_eq!((in_code, in_memory), (12, 7));
+    }
 }
 
 #[cfg(test)]
@@ -138,25 +225,25 @@ mod test_part2 {
 
     #[test]
     fn empty_string() {
-        let (in_code, in_memory) = string_lengths_part2("\"\"");
+        let (in_code, in_memory) = string_lengths_part2("\"\"").unwrap();
         assert_eq!((in_code, in_memory), (2, 6));
     }
 
     #[test]
     fn abc() {
-        let (in_code, in_memory) = string_lengths_part2("\"abc\"");
+        let (in_code, in_memory) = string_lengths_part2("\"abc\"").unwrap();
         assert_eq!((in_code, in_memory), (5, 9));
     }
 
     #[test]
     fn with_escaped_quote() {
-        let (in_code, in_memory) = string_lengths_part2("\"aaa\\\"aaa\"");
+        let (in_code, in_memory) = string_lengths_part2("\"aaa\\\"aaa\"").unwrap();
         assert_eq!((in_code, in_memory), (10, 16));
     }
 
     #[test]
     fn escaped_apostrophe() {
-        let (in_code, in_memory) = string_lengths_part2("\"\\x27\"");
+        let (in_code, in_memory) = string_lengths_part2("\"\\x27\"").unwrap();
         assert_eq!((in_code, in_memory), (6, 11));
     }
 
@@ -164,7 +251,25 @@ mod test_part2 {
     fn samples() {
         let input = include_str!("./sample.txt");
         let lines: Vec<&str> = input.lines().collect();
-        let (in_code, in_memory) = process_list_part2(&lines);
+        let (in_code, in_memory) = process_list_part2(&lines).unwrap();
         assert_eq!((in_code, in_memory), (23, 42));
     }
+
+    #[test]
+    fn invalid_lines() {
+        assert_eq!(
+            process_list_part2(&["\"abc\"", ""]),
+            Err("line 2: empty literal".to_string())
+        );
+        assert_eq!(
+            process_list_part2(&["abc\""]),
+            Err("line 1: missing opening quote at byte 0".to_string())
+        );
+    }
+
+    #[test]
+    fn multibyte() {
+        let (in_code, encoded) = string_lengths_part2("\"é\"").unwrap();
+        assert_eq!((in_code, encoded), (4, 14));
+    }
 }